@@ -2,5 +2,8 @@ FROM openjdk:11
 
 WORKDIR /app
 RUN apt-get update && apt-get install -y curl
+# Allow-listed jars from the build context are put on the classpath
+COPY packages/ /packages/
+ENV CLASSPATH=".:/packages/*"
 EXPOSE 5000
 CMD ["tail", "-f", "/dev/null"]
\ No newline at end of file
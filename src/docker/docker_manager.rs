@@ -2,19 +2,21 @@ use bollard::{
     Docker,
     container::{Config as ContainerConfig, CreateContainerOptions, StartContainerOptions},
     exec::{CreateExecOptions, StartExecResults},
-    image::BuildImageOptions,
+    image::{BuildImageOptions, CreateImageOptions},
     models::{HostConfig, PortBinding},
 };
 use futures_util::stream::StreamExt;
-use std::{error::Error, str::FromStr};
-use tokio::io::AsyncReadExt;
+use std::{error::Error, time::Duration};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 use crate::{
     models::{
         cleanup_models::{ActivityType, CleanupService},
-        docker_models::DockerSupportedLanguage,
-        validation_models::ValidationError,
+        executor_models::{ExecutionResult, OutputChunk, OutputStream, RunOptions},
+        language_registry_models::LanguageDefinition,
+        session_management_models::{RunDisposition, SessionContainer},
     },
     services::{
         all_session_services::session_management_service::SessionManagement,
@@ -24,56 +26,113 @@ use crate::{
     utils::{docker_utils::get_docker_instance, tar_utils::create_tar_archive},
 };
 
+// Where each run records its shell's pid, so a timeout, a newer run or a `cancel` can kill it
+const RUN_PID_FILE: &str = "/tmp/.rexec_run.pid";
+// Kills the recorded shell and its direct children (the interpreter / compiler) with plain
+// `sh` builtins, since slim images don't ship pkill. The shell is stopped first so it can't
+// start its next command in between.
+const KILL_RUN_COMMAND: &str = "pid=$(cat /tmp/.rexec_run.pid 2>/dev/null) || exit 0; kill -STOP \"$pid\"; \
+for stat in /proc/[0-9]*/stat; do read -r cpid _ _ ppid _ < \"$stat\" 2>/dev/null \
+&& [ \"$ppid\" = \"$pid\" ] && kill -9 \"$cpid\"; done; kill -9 \"$pid\"; rm -f /tmp/.rexec_run.pid";
+
 pub async fn handle_request(
     session_id: &str,
-    language: &str,
+    language_definition: &LanguageDefinition,
     code: &str,
-) -> Result<String, Box<dyn Error>> {
-    let docker_language = match DockerSupportedLanguage::from_str(language) {
-        Ok(lang) => lang,
-        Err(_) => {
-            eprintln!("Unsupported language: {}", language);
-            return Err(Box::new(ValidationError::InvalidLanguage(
-                language.to_string(),
-            )));
-        }
-    };
+    cancel: &CancellationToken,
+    options: &RunOptions,
+) -> Result<ExecutionResult, Box<dyn Error>> {
     let docker = get_docker_instance()?;
     //Docker::connect_with_local_defaults()?;
     println!("Got docker instance");
-    let config = get_global_config(|config| config.clone()).await;
-    // Select the appropriate Dockerfile
-    let dockerfile_path = match DockerSupportedLanguage::from_str(language) {
-        Ok(DockerSupportedLanguage::Python) => &config.dockerfiles.python,
-        Ok(DockerSupportedLanguage::JavaScript) => &config.dockerfiles.javascript,
-        Ok(DockerSupportedLanguage::Java) => &config.dockerfiles.java,
-        // Ok(DockerSupportedLanguage::Go) => &config.dockerfiles.go,
-        _ => return Err(format!("Unsupported language: {}", language).into()),
-    };
-    println!("{:?} docker file path", dockerfile_path);
     // Build and run the container
-    let container_name =
-        build_and_run_container(session_id, &docker, dockerfile_path, language).await?;
+    let container_name = build_and_run_container(session_id, &docker, language_definition).await?;
 
     // Execute the code inside the container
-    let result =
-        execute_code_in_new_container(&docker, &container_name, docker_language, code).await?;
+    let session_service = get_global_config(|config| config.clone())
+        .await
+        .session_management_service
+        .unwrap();
+    let language_key = language_definition.key();
+    let session = session_service
+        .get_session(session_id, &language_key)
+        .await
+        .map_err(|e| e.message())?;
+    let container = session
+        .container
+        .clone()
+        .ok_or_else(|| format!("No container attached to session '{}'", session_id))?;
+    session_service
+        .begin_execution(session_id, &language_key)
+        .await;
+    let output = execute_code_in_new_container(
+        &docker,
+        &container_name,
+        language_definition,
+        code,
+        &container.image_digest,
+        cancel,
+        options,
+    )
+    .await
+    .map_err(|e| e.to_string()); // Box<dyn Error> isn't Send, don't hold it across .await
+    session_service
+        .end_execution(session_id, &language_key)
+        .await;
+    let output = output?;
 
-    Ok(result)
+    Ok(ExecutionResult {
+        output,
+        runtime_version: container.runtime_version,
+        session_ttl_secs: session.remaining_ttl().as_secs(),
+        disposition: RunDisposition::Immediate,
+        environment_lost: None,
+    })
 }
 
-pub async fn build_and_run_container(
+/// Prepares the image for a language: builds its Dockerfile when one is configured,
+/// otherwise pulls the configured image as-is. Returns the image name to run and whether
+/// it was built for this session only.
+async fn prepare_image(
     session_id: &str,
     docker: &Docker,
-    dockerfile_path: &str,
-    language: &str,
-) -> Result<String, Box<dyn Error>> {
-    println!("Building and running container for language: {}", language);
+    language: &LanguageDefinition,
+) -> Result<(String, bool), Box<dyn Error>> {
     let config = get_global_config(|config| config.clone()).await;
-    println!("Config received image under process");
+    let dockerfile_path = match (&language.config.dockerfile, &language.config.image) {
+        (Some(dockerfile), _) => dockerfile,
+        (None, Some(image)) => {
+            println!("Pulling image '{}' for {}", image, language.name);
+            let mut pull_stream = docker.create_image(
+                Some(CreateImageOptions {
+                    from_image: image.as_str(),
+                    ..Default::default()
+                }),
+                None,
+                None,
+            );
+            while let Some(pull_output) = pull_stream.next().await {
+                if let Err(e) = pull_output {
+                    eprintln!("Error during image pull: {}", e);
+                    return Err(Box::new(e));
+                }
+            }
+            return Ok((image.clone(), false));
+        }
+        (None, None) => {
+            return Err(format!(
+                "Language '{}' has neither a dockerfile nor an image configured",
+                language.name
+            )
+            .into());
+        }
+    };
+    println!("{:?} docker file path", dockerfile_path);
     let image_name = format!(
         "{}_{}_{}",
-        config.constants.executor_image_name, session_id, language
+        config.constants.executor_image_name,
+        session_id,
+        language.slug()
     );
     // Create tar archive for build context
     println!("image name {:?}", image_name);
@@ -83,23 +142,37 @@ pub async fn build_and_run_container(
         "{}{}_{}_{}",
         tar_path_base,
         Uuid::new_v4(),
-        language,
+        language.slug(),
         &config.constants.tar_file_name
     );
     let docker_file_name = &config.constants.dockerfile;
-    let dockerfile_name =
-        create_tar_archive(dockerfile_path, &tar_path_formatted, docker_file_name)?;
+    let dockerfile_name = create_tar_archive(
+        dockerfile_path,
+        &tar_path_formatted,
+        docker_file_name,
+        language.config.package_cache.as_deref(),
+    )?;
     println!("Using dockerfile_name: '{}'", dockerfile_name);
     // Use a sync File, not tokio::fs::File, because bollard expects a blocking Read stream
     let mut file = tokio::fs::File::open(tar_path_formatted).await?;
 
     let mut contents = Vec::new();
     file.read_to_end(&mut contents).await?;
+    // Allow-listed packages are installed from the `packages/` dir of the build context
+    let packages = language.config.packages.join(" ");
     // Build image options
     let build_options = BuildImageOptions {
         dockerfile: dockerfile_name,
         t: image_name.clone(),
         rm: true,
+        buildargs: [("PACKAGES".to_string(), packages)].into_iter().collect(),
+        // Labelled like the containers so reconciliation can find images left by a crash
+        labels: [(
+            config.constants.docker_created_by_label.clone(),
+            config.build.service_name.clone(),
+        )]
+        .into_iter()
+        .collect(),
         ..Default::default()
     };
 
@@ -135,6 +208,19 @@ pub async fn build_and_run_container(
             println!("Tar file cleaned up successfully.");
         }
     });
+    Ok((image_name, true))
+}
+
+pub async fn build_and_run_container(
+    session_id: &str,
+    docker: &Docker,
+    language: &LanguageDefinition,
+) -> Result<String, Box<dyn Error>> {
+    println!(
+        "Building and running container for language: {}",
+        language.name
+    );
+    let (image_name, per_session_image) = prepare_image(session_id, docker, language).await?;
 
     // Create container config
 
@@ -144,7 +230,7 @@ pub async fn build_and_run_container(
             .await
             .constants
             .executor_container_name,
-        language,
+        language.slug(),
         session_id
     );
     let created_by_tag = get_global_config(|config| config.clone())
@@ -162,6 +248,7 @@ pub async fn build_and_run_container(
         labels: Some([(created_by_tag, label)].iter().cloned().collect()),
         image: Some(image_name.clone()),
         host_config: Some(HostConfig {
+            memory: Some(language.config.limits.memory_mb * 1024 * 1024),
             port_bindings: Some(
                 [(
                     "5001/tcp".to_string(),
@@ -179,7 +266,7 @@ pub async fn build_and_run_container(
         ..Default::default()
     };
     // Create container
-    docker
+    let created = docker
         .create_container(
             Some(CreateContainerOptions {
                 name: &container_name,
@@ -196,28 +283,58 @@ pub async fn build_and_run_container(
         .await?;
     println!("Container '{}' started successfully!", container_name);
 
+    let image_digest = docker
+        .inspect_image(&image_name)
+        .await
+        .ok()
+        .and_then(|image| image.id)
+        .unwrap_or_else(|| image_name.clone());
+    let runtime_version = detect_runtime_version(docker, &container_name, language).await;
+    println!(
+        "Runtime version for {} in '{}': {}",
+        language.key(),
+        container_name,
+        runtime_version
+    );
+
     // Store session info
     let session_service = &get_global_config(|config| config.clone())
         .await
         .session_management_service;
 
-    session_service
+    let saved = session_service
         .unwrap()
-        .add_session(
-            session_id.to_string(),
-            language.to_string(),
-            container_name.clone(),
+        .attach_container(
+            session_id,
+            &language.key(),
+            SessionContainer::new(
+                container_name.clone(),
+                created.id,
+                runtime_version,
+                image_digest,
+                per_session_image.then(|| image_name.clone()),
+            ),
         )
-        .await
-        .map_err(|e| format!("Failed to save session: {}", e.message()))?;
+        .await;
+    if let Err(e) = saved {
+        eprintln!("Failed to save session: {}", e.message());
+        // Nothing references the new container now, so don't leave it running
+        let session_image = per_session_image.then(|| image_name.clone());
+        let orphan = container_name.clone();
+        tokio::spawn(async move {
+            CleanupService::remove_session_resources(&orphan, session_image.as_deref()).await;
+        });
+        return Err(Box::new(e));
+    }
     println!(
         "Session stored successfully for ID '{}', language '{}'",
-        session_id, language
+        session_id,
+        language.key()
     );
     // FOR TESTING PURPOSES: Retrieve and print session image
     match session_service
         .unwrap()
-        .get_session_image(session_id, language)
+        .get_session_image(session_id, &language.key())
         .await
     {
         Ok(image) => {
@@ -233,66 +350,108 @@ pub async fn build_and_run_container(
 async fn execute_code_in_new_container(
     docker: &Docker,
     container_name: &str,
-    language: DockerSupportedLanguage,
+    language: &LanguageDefinition,
     code: &str,
+    image_digest: &str,
+    cancel: &CancellationToken,
+    options: &RunOptions,
 ) -> Result<String, Box<dyn Error>> {
-    let shell_command = generate_shell_command(language, code)
+    let shell_command = generate_shell_command(language, code, image_digest)
+        .await
         .map_err(|e| format!("Failed to generate shell command: {}", e))?; //format!("echo '{}' > script.py && python script.py", code);
-    let exec_options = CreateExecOptions {
-        cmd: Some(vec!["sh", "-c", &shell_command]),
-        attach_stdout: Some(true),
-        attach_stderr: Some(true),
-        ..Default::default()
-    };
-
-    let exec = docker.create_exec(container_name, exec_options).await?;
-    let output = docker.start_exec(&exec.id, None).await?;
-
-    match output {
-        StartExecResults::Attached { mut output, .. } => {
-            let mut result = String::new();
-            while let Some(Ok(log)) = output.next().await {
-                match log {
-                    bollard::container::LogOutput::StdOut { message } => {
-                        result.push_str(&String::from_utf8_lossy(&message));
-                    }
-                    bollard::container::LogOutput::StdErr { message } => {
-                        result.push_str(&String::from_utf8_lossy(&message));
-                    }
-                    _ => {}
-                }
-            }
-            Ok(result)
-        }
-        _ => Err("Failed to execute code in container".into()),
-    }
+    run_exec(
+        docker,
+        container_name,
+        &shell_command,
+        language.config.limits.timeout_secs,
+        Some(cancel),
+        Some(options),
+    )
+    .await
 }
 
 /// Executes code in an existing, already running container.
 /// You can call this function with the container name/id and code to execute.
 ///
 /// # Arguments
-/// * `docker` - Reference to the Docker client
 /// * `container_name` - Name or ID of the running container
+/// * `language` - Registry entry describing how to compile and run the code
 /// * `code` - The code to execute inside the container
+/// * `image_digest` - Image id of the container, used to key cached compiler output
+/// * `cancel` - Cancelled when a newer run of the session takes over; the run is killed
+/// * `options` - Stdin for the run and where to stream its output
 ///
 /// # Returns
 /// * `Result<String, Box<dyn Error>>` - Output from the code execution or error
 pub async fn execute_code_in_existing_container(
     container_name: &str,
-    language: DockerSupportedLanguage,
+    language: &LanguageDefinition,
     code: &str,
+    image_digest: &str,
+    cancel: &CancellationToken,
+    options: &RunOptions,
 ) -> Result<String, Box<dyn Error>> {
     let docker = get_docker_instance()?;
-    // update this to accept multiple langeages
-    let shell_command = generate_shell_command(language, code)
+    let shell_command = generate_shell_command(language, code, image_digest)
+        .await
         .map_err(|e| format!("Failed to generate shell command: {}", e))?;
     println!(
         "Executing code in existing container '{}': {}",
         container_name, shell_command
     );
+    run_exec(
+        &docker,
+        container_name,
+        &shell_command,
+        language.config.limits.timeout_secs,
+        Some(cancel),
+        Some(options),
+    )
+    .await
+}
+
+/// Asks the container which interpreter/compiler version it actually runs, using the
+/// language's `version_command`. Falls back to the requested version if that fails.
+async fn detect_runtime_version(
+    docker: &Docker,
+    container_name: &str,
+    language: &LanguageDefinition,
+) -> String {
+    let requested = language.version.clone().unwrap_or_default();
+    let version_command = match &language.config.version_command {
+        Some(command) => command,
+        None => return requested,
+    };
+    match run_exec(docker, container_name, version_command, 10, None, None).await {
+        Ok(output) if !output.trim().is_empty() => {
+            output.lines().next().unwrap_or("").trim().to_string()
+        }
+        Ok(_) => requested,
+        Err(e) => {
+            eprintln!("Failed to detect runtime version: {}", e);
+            requested
+        }
+    }
+}
+
+/// Runs a shell command inside a container and collects stdout/stderr,
+/// giving up once the language's `timeout_secs` limit has passed. If the limit passes or
+/// `cancel` fires first, the command's processes are killed before returning. `options` feeds the command's stdin
+/// and receives its output chunk by chunk.
+async fn run_exec(
+    docker: &Docker,
+    container_name: &str,
+    shell_command: &str,
+    timeout_secs: u64,
+    cancel: Option<&CancellationToken>,
+    options: Option<&RunOptions>,
+) -> Result<String, Box<dyn Error>> {
+    let stdin = options.and_then(|options| options.stdin.as_deref());
+    let output_sink = options.and_then(|options| options.output.as_ref());
+    let shell_command = format!("echo $$ > {}; {}", RUN_PID_FILE, shell_command);
     let exec_options = CreateExecOptions {
-        cmd: Some(vec!["sh", "-c", &shell_command]),
+        cmd: Some(vec!["sh", "-c", shell_command.as_str()]),
+        attach_stdin: Some(stdin.is_some()),
         attach_stdout: Some(true),
         attach_stderr: Some(true),
         ..Default::default()
@@ -302,21 +461,98 @@ pub async fn execute_code_in_existing_container(
     let output = docker.start_exec(&exec.id, None).await?;
 
     match output {
-        StartExecResults::Attached { mut output, .. } => {
+        StartExecResults::Attached {
+            mut output,
+            mut input,
+        } => {
             let mut result = String::new();
-            while let Some(Ok(log)) = output.next().await {
-                match log {
-                    bollard::container::LogOutput::StdOut { message } => {
-                        result.push_str(&String::from_utf8_lossy(&message));
+            // Written alongside reading the output so a chatty program can't block on a full pipe
+            let feed = async {
+                if let Some(stdin) = stdin {
+                    // Closing stdin gives the program EOF
+                    let fed = match input.write_all(stdin.as_bytes()).await {
+                        Ok(()) => input.shutdown().await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = fed {
+                        eprintln!("Failed to write stdin in '{}': {}", container_name, e);
+                    }
+                }
+            };
+            let read = async {
+                while let Some(Ok(log)) = output.next().await {
+                    let (stream, message) = match log {
+                        bollard::container::LogOutput::StdOut { message } => {
+                            (OutputStream::Stdout, message)
+                        }
+                        bollard::container::LogOutput::StdErr { message } => {
+                            (OutputStream::Stderr, message)
+                        }
+                        _ => continue,
+                    };
+                    let data = String::from_utf8_lossy(&message);
+                    result.push_str(&data);
+                    if let Some(sink) = output_sink {
+                        // The receiver may be gone (e.g. the socket closed); the run goes on
+                        let _ = sink.send(OutputChunk {
+                            stream,
+                            data: data.to_string(),
+                        });
                     }
-                    bollard::container::LogOutput::StdErr { message } => {
-                        result.push_str(&String::from_utf8_lossy(&message));
+                }
+            };
+            let collect = async {
+                tokio::join!(feed, read);
+            };
+            let cancelled = async {
+                match cancel {
+                    Some(token) => token.cancelled().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                finished = tokio::time::timeout(Duration::from_secs(timeout_secs), collect) => {
+                    if finished.is_err() {
+                        println!("Run in '{}' timed out, killing it", container_name);
+                        kill_run(docker, container_name).await;
+                        return Err(
+                            format!("Execution timed out after {} seconds", timeout_secs).into(),
+                        );
                     }
-                    _ => {}
+                }
+                _ = cancelled => {
+                    println!("Run in '{}' cancelled, killing it", container_name);
+                    kill_run(docker, container_name).await;
+                    return Err("Execution cancelled".into());
                 }
             }
             Ok(result)
         }
-        _ => Err("Failed to execute code in existing container".into()),
+        _ => Err("Failed to execute code in container".into()),
+    }
+}
+
+// Kills the run recorded in the container's pid file. Not done through `run_exec`, which
+// would record the kill command's own pid first.
+async fn kill_run(docker: &Docker, container_name: &str) {
+    let killed = async {
+        let exec_options = CreateExecOptions {
+            cmd: Some(vec!["sh", "-c", KILL_RUN_COMMAND]),
+            attach_stdout: Some(true),
+            attach_stderr: Some(true),
+            ..Default::default()
+        };
+        let exec = docker.create_exec(container_name, exec_options).await?;
+        if let StartExecResults::Attached { mut output, .. } =
+            docker.start_exec(&exec.id, None).await?
+        {
+            while output.next().await.is_some() {}
+        }
+        Ok::<(), bollard::errors::Error>(())
+    };
+    match tokio::time::timeout(Duration::from_secs(10), killed).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => eprintln!("Failed to kill run in '{}': {}", container_name, e),
+        Err(_) => eprintln!("Timed out killing run in '{}'", container_name),
     }
 }
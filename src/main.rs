@@ -8,12 +8,13 @@ use proto::executor::code_executor_server::CodeExecutorServer;
 
 use crate::services::{
     all_session_services::{
-        session_management_service::SessionManagement,
-        session_service::get_session_management_service,
+        session_management_service::SessionManagement, session_store_service::get_session_store,
     },
     helper_services::{
         cleanup_service,
         config_service::{get_global_config, set_global_config},
+        container_events_service::watch_container_events,
+        metrics_service::get_metrics,
     },
     websocket::websocket_server::run_websocket_server,
 };
@@ -50,6 +51,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     config.init();
     config.build.service_name = format!("{} {}", config.build.service_name, server_pod_id);
     set_global_config(config);
+
+    // Pick up the sessions a previous run left in the session store
+    let startup_config = get_global_config(|config| config.clone()).await;
+    if let Some(session_management_service) = startup_config.session_management_service {
+        session_management_service.restore_sessions().await;
+        // Whatever a crashed instance left behind that no restored session owns
+        CleanupService::reconcile_orphans(session_management_service).await;
+    }
     let ports_service = ps::new().await; //ports_service::PortsService::new();
     let address = ports_service.get_grpc_server_address();
     println!("gRPC server address: {}", address); // Add this line
@@ -88,8 +97,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .session_management_service
                 .clone();
 
-            let _ = get_session_management_service();
-
             tokio::spawn(async move {
                 let cleanup_interval = Duration::from_secs(
                     get_global_config(|config| config.clone())
@@ -122,6 +129,46 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             });
 
+            tokio::spawn(async move {
+                let config = get_global_config(|config| config.clone()).await;
+                let reconcile_interval =
+                    Duration::from_secs(config.session_configs.reconcile_interval);
+                let Some(session_management_service) = config.session_management_service else {
+                    return;
+                };
+                loop {
+                    tokio::time::sleep(reconcile_interval).await;
+                    CleanupService::reconcile_orphans(session_management_service).await;
+                    println!("Periodic reconciliation completed.");
+                }
+            });
+
+            tokio::spawn(async move {
+                let config = get_global_config(|config| config.clone()).await;
+                let idle_pause_after = config.session_configs.idle_pause_after;
+                let Some(session_management_service) = config.session_management_service else {
+                    return;
+                };
+                if idle_pause_after == 0 {
+                    return;
+                }
+                // Check twice per idle period so a container is paused at most 1.5x late
+                let check_interval = Duration::from_secs((idle_pause_after / 2).max(1));
+                loop {
+                    tokio::time::sleep(check_interval).await;
+                    session_management_service
+                        .pause_idle_sessions(Duration::from_secs(idle_pause_after))
+                        .await;
+                }
+            });
+
+            tokio::spawn(async move {
+                let config = get_global_config(|config| config.clone()).await;
+                if let Some(session_management_service) = config.session_management_service {
+                    watch_container_events(session_management_service).await;
+                }
+            });
+
             // Create a shutdown signal future
             let shutdown_signal = async {
                 signal::ctrl_c()
@@ -151,14 +198,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             // Perform cleanup operations
             // Cleanup logic here
             let cleanup_service = CleanupService {};
+            get_session_store().flush();
+            // Persisted sessions are reattached to their containers on the next start
+            let container = (!get_session_store().is_persistent()).then(|| container.to_string());
             let activity = ActivityType::new(
-                Some(container.to_string()),
+                container,
                 None,
                 Some(all_tars.to_string()),
                 None,
                 Some(ports_service.get_all_ports()),
             );
             cleanup_service.cleanup(activity).await?;
+            println!("Metrics:\n{}", get_metrics().report());
 
             println!("Server exited cleanly.");
         }
@@ -1,4 +1,29 @@
+use std::collections::VecDeque;
+use tree_sitter::Tree;
+
+use crate::models::websocket_message_model::Patch;
+
 #[derive(Debug, Clone)]
 pub struct CodeEditorModel {
     pub lines: Vec<String>, // Mutable representation of code
+    pub revision: u64,      // bumped on every change to `lines`, reported in WebSocket acks
+    // Recent changes, oldest first; stale patches are transformed through them
+    pub history: VecDeque<EditRecord>,
+    // Last parse of the buffer, edited along with every patch since so the next parse only
+    // redoes what changed; `None` before the first validation and after a full replacement
+    pub syntax_tree: Option<Tree>,
+}
+
+// One change to the buffer, the one that produced `revision`.
+#[derive(Debug, Clone)]
+pub struct EditRecord {
+    pub revision: u64,
+    pub patches: Option<Vec<Patch>>, // as applied, in order; `None` when the whole buffer was replaced
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    Invalid(String),
+    // `base` is too old to transform the patch to `current`; the client has to resync
+    Stale { base: u64, current: u64 },
 }
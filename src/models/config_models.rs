@@ -1,22 +1,67 @@
 use core::str;
+use std::collections::HashMap;
 
 use crate::models::{
-    in_memory_session_cache_model::SessionCache,
+    language_registry_models::LanguageRegistry,
     session_management_models::SessionManagementService,
     websocket_sessionpool_models::ConnectionManager,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct LanguageLimits {
+    pub timeout_secs: u64,
+    pub memory_mb: i64,
+}
+
+impl Default for LanguageLimits {
+    fn default() -> Self {
+        LanguageLimits {
+            timeout_secs: 10,
+            memory_mb: 256,
+        }
+    }
+}
+
+// A `[languages.<name>.versions."<version>"]` table; overrides where the image comes from.
+#[derive(Debug, Deserialize, Clone)]
+pub struct LanguageVersionConfig {
+    pub dockerfile: Option<String>,
+    pub image: Option<String>,
+}
+
+// One `[languages.<name>]` table in config.toml.
+// `{file}` and `{stem}` in the commands are replaced with the source file name.
 #[derive(Debug, Deserialize, Clone)]
-pub struct Dockerfiles {
-    pub python: String,
-    pub javascript: String,
-    pub java: String,
+pub struct LanguageConfig {
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub file_extension: String,
+    pub source_file: String,
+    pub dockerfile: Option<String>,
+    pub image: Option<String>,
+    pub compile_command: Option<String>,
+    pub run_command: String,
+    #[serde(default)]
+    pub limits: LanguageLimits,
+    pub grammar: Option<String>,
+    pub default_version: Option<String>,
+    pub version_command: Option<String>,
+    #[serde(default)]
+    pub versions: HashMap<String, LanguageVersionConfig>,
+    #[serde(default)]
+    pub packages: Vec<String>, // allow-listed third-party packages, baked into the image
+    // Import names of packages whose name differs from what code imports, e.g.
+    // "scikit-learn" = ["sklearn"]; other packages are imported by their own name
+    #[serde(default)]
+    pub package_imports: HashMap<String, Vec<String>>,
+    pub package_cache: Option<String>, // local wheel / npm cache dir copied into the build context
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Paths {
     pub tar_path: String,
+    pub build_cache_path: String, // inside the session container
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -38,32 +83,54 @@ pub struct Build {
     pub host: String,
 }
 
+// What a run does when another run of the same session is still going.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConcurrentRunPolicy {
+    Queue,  // wait for it to finish
+    Reject, // fail with ABORTED
+    Cancel, // kill it and run instead
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct SessionConfigs {
     pub session_timeout: u64,
     pub session_cleanup_interval: u64,
     pub max_sessions: usize,
-    // pub session_image_prefix: String,
+    pub teardown_retries: u32, // attempts to remove a session's container/image
+    pub busy_retry_after: u64, // retry-after hint (secs) when max_sessions are all busy
+    pub reconcile_interval: u64, // secs between sweeps for containers/images no session owns
+    pub orphan_grace_period: u64, // secs an unowned container/image is left alone after creation
+    pub concurrent_run_policy: ConcurrentRunPolicy,
+    pub idle_pause_after: u64, // secs without use before a session's container is paused, 0 = never
+    pub recreate_lost_containers: bool, // start a new container for a session whose one died
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreBackend {
+    Memory, // sessions are lost on restart
+    File,   // append-only log at `path`, reloaded on startup
+}
 
-pub struct WebSocketPoolConfig {
-    pub max_connections: usize,
+#[derive(Debug, Deserialize, Clone)]
+pub struct SessionStoreConfig {
+    pub backend: SessionStoreBackend,
+    pub path: String,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
-    pub dockerfiles: Dockerfiles,
+    pub languages: HashMap<String, LanguageConfig>,
     pub paths: Paths,
     pub constants: Constants,
     pub build: Build,
     pub session_configs: SessionConfigs,
-    pub websocket_pool_config: WebSocketPoolConfig,
+    pub session_store: SessionStoreConfig,
     #[serde(skip)]
     pub session_management_service: Option<&'static SessionManagementService>,
     #[serde(skip)]
-    pub session_cache_service: Option<&'static SessionCache>,
-    #[serde(skip)]
     pub websocket_seesion_pool: Option<&'static ConnectionManager>,
+    #[serde(skip)]
+    pub language_registry: Option<&'static LanguageRegistry>,
 }
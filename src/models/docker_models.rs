@@ -1,3 +1,5 @@
+// Built-in tree-sitter grammars. Runnable languages come from the
+// `[languages.*]` registry in config.toml and reference these via `grammar`.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DockerSupportedLanguage {
     Python,
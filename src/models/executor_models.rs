@@ -1,2 +1,37 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{models::session_management_models::RunDisposition, proto::executor::SessionLimits};
+
 #[derive(Debug, Default, Clone)]
 pub struct ExecutorService;
+
+#[derive(Debug, Clone)]
+pub struct ExecutionResult {
+    pub output: String,
+    pub runtime_version: String,
+    pub session_ttl_secs: u64,
+    pub disposition: RunDisposition,
+    pub environment_lost: Option<String>, // why the previous container died, if this run replaced it
+}
+
+// What a run gets besides its code.
+#[derive(Debug, Default)]
+pub struct RunOptions {
+    pub stdin: Option<String>,
+    pub limits: Option<SessionLimits>, // lowers the session's limits for this run, 0 keeps them
+    pub output: Option<UnboundedSender<OutputChunk>>, // receives output as the run produces it
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug)]
+pub struct OutputChunk {
+    pub stream: OutputStream,
+    pub data: String,
+}
@@ -1,18 +1,12 @@
-use dashmap::DashMap;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
 
-use crate::models::{code_editor_models::CodeEditorModel, docker_models::DockerSupportedLanguage};
-
-#[derive(Debug, Clone)]
-pub struct Session {
-    pub session_id: String,
-    pub language: DockerSupportedLanguage,
-    pub editor: CodeEditorModel,
-}
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SessionErrorType {
     NotFound,
     InvalidInput,
+    Conflict,  // the edit was based on an outdated buffer
+    Forbidden, // an observer tried to change the session
     InternalError,
 }
 
@@ -21,7 +15,3 @@ pub struct SessionError {
     pub error_type: SessionErrorType,
     pub error_code: u16,
 }
-#[derive(Debug)]
-pub struct SessionCache {
-    pub sessions: Arc<DashMap<String, Session>>,
-}
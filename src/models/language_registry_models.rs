@@ -0,0 +1,16 @@
+use std::collections::HashMap;
+
+use crate::models::config_models::LanguageConfig;
+
+#[derive(Debug, Clone)]
+pub struct LanguageDefinition {
+    pub name: String,
+    pub version: Option<String>,
+    pub config: LanguageConfig, // dockerfile/image already resolved for `version`
+}
+
+#[derive(Debug)]
+pub struct LanguageRegistry {
+    pub languages: HashMap<String, LanguageConfig>,
+    pub aliases: HashMap<String, String>, // alias -> canonical language name
+}
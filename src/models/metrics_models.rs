@@ -0,0 +1,15 @@
+use dashmap::DashMap;
+
+// In-process counters and timings, printed on shutdown.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub counters: DashMap<String, u64>,
+    pub timings: DashMap<String, TimingStat>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct TimingStat {
+    pub count: u64,
+    pub total_ms: u128,
+    pub max_ms: u128,
+}
@@ -4,8 +4,11 @@ pub mod config_models;
 pub mod docker_models;
 pub mod executor_models;
 pub mod in_memory_session_cache_model;
+pub mod language_registry_models;
+pub mod metrics_models;
 pub mod port_models;
 pub mod session_management_models;
+pub mod session_store_models;
 pub mod validation_models;
 pub mod websocket_message_model;
 pub mod websocket_sessionpool_models;
@@ -1,9 +1,16 @@
+use serde::{Deserialize, Serialize};
 use std::cmp::Reverse;
 use std::collections::{BinaryHeap, HashMap};
 use std::hash::Hash;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tokio_util::sync::CancellationToken;
+
+use crate::models::{
+    code_editor_models::CodeEditorModel, config_models::LanguageLimits,
+    session_store_models::SessionStore,
+};
 
 #[derive(Debug)]
 pub enum SessionError {
@@ -11,6 +18,12 @@ pub enum SessionError {
     InvalidLanguage(String),
     ExecutionError(String),
     Unauthenticated(String),
+    ResourceExhausted(u64), // all sessions busy; seconds the client should wait before retrying
+    Busy(String),           // a run is in progress and the policy is `reject`
+    Cancelled(String),      // the run was cancelled by a newer run or a WebSocket `cancel`
+    EnvironmentLost(String), // the session's container died or was removed; holds the reason
+    InvalidPatch(String),
+    StaleRevision(u64, u64), // a patch's base revision and the buffer's, too far apart to transform
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -19,15 +32,55 @@ pub struct SessionKey {
     pub language: String,
 }
 
+// The container a session runs code in; started on the session's first run.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionContainer {
+    pub name: String, // reused when the session's container is recreated
+    #[serde(default)]
+    pub id: String, // Docker's id, unique to this container; empty until a restore inspects it
+    pub runtime_version: String,
+    pub image_digest: String, // id of the image the container runs, part of the compile cache key
+    pub session_image: Option<String>, // image built only for this session, removed with it
+}
+
+// One session, shared by the gRPC and WebSocket paths. The language is part of its `SessionKey`.
 #[derive(Clone, Debug)]
 pub struct SessionValue {
-    pub image: String,
+    pub editor: CodeEditorModel, // current buffer; what a run without code executes
+    pub container: Option<SessionContainer>,
+    pub paused: bool, // container paused while idle, unpaused by the next run
+    pub environment_lost: Option<String>, // why the container died, from the Docker events stream
+    pub owner: Option<String>, // set by CreateSession, used to filter ListSessions
+    pub limits: Option<LanguageLimits>, // overrides the language's limits, never above them
+    pub expires_at: Instant, // slides forward on every use, see `SessionManagementService::touch`
+    pub last_active: Instant,
+    pub active_executions: u32, // sessions with running executions are never evicted
+    pub run_lock: Arc<Mutex<()>>, // held for the whole run, see `SessionManagement::acquire_run`
+    pub current_run: Option<CancellationToken>,
+}
+
+// How a run got its turn on the session; reported in `ExecuteResponse.concurrency`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RunDisposition {
+    Immediate,
+    Queued,
+    CancelledPrevious,
+}
+
+// Exclusive right to run code in a session, released when dropped.
+pub struct RunPermit {
+    pub _guard: OwnedMutexGuard<()>,
+    pub token: CancellationToken, // cancelled by a newer run under `cancel`, or by `cancel_run`
+    pub disposition: RunDisposition,
 }
 
 #[derive(Clone, Debug)]
 pub struct SessionManagementService {
-    pub ttl: Duration, // Default TTL of 1 hour
+    pub ttl: Duration, // session_configs.session_timeout
     pub sessions: Arc<Mutex<HashMap<SessionKey, SessionValue>>>,
-    pub expirations: Arc<Mutex<BinaryHeap<Reverse<(Instant, String)>>>>, // Min-heap for expiration times
+    // Min-heap for expiration times. Refreshing a session pushes a new entry instead of
+    // updating the old one; stale entries are skipped when popped (lazy invalidation).
+    pub expirations: Arc<Mutex<BinaryHeap<Reverse<(Instant, String)>>>>,
     pub last_cleanup: Arc<Mutex<Instant>>,
+    pub store: &'static dyn SessionStore, // every add / refresh / delete is written through
 }
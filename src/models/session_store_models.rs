@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex, mpsc};
+
+use dashmap::DashMap;
+
+use crate::models::{config_models::LanguageLimits, session_management_models::SessionContainer};
+
+// A session as persisted: its buffer and enough to find its container again after a restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredSession {
+    pub session_key: String, // `SessionKey::to_string`, "<session_id>:<language>"
+    pub code: String,
+    #[serde(default)]
+    pub revision: u64,
+    pub container: Option<SessionContainer>,
+    pub expires_at_unix: u64, // wall-clock seconds, `Instant`s don't survive a restart
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub limits: Option<LanguageLimits>,
+}
+
+// One line of the append-only log; replaying the lines in order rebuilds the store.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum SessionLogEntry {
+    Put { session: Box<StoredSession> },
+    Delete { key: String },
+}
+
+/// Where session state is kept between requests (and, for persistent stores, restarts).
+/// Writes are best effort: a failing store is logged and never fails the request.
+pub trait SessionStore: Send + Sync + Debug {
+    fn save(&self, session: StoredSession);
+    fn remove(&self, session_key: &str);
+    fn load_all(&self) -> Vec<StoredSession>;
+    // Whether sessions outlive the process, i.e. their containers should be kept on shutdown
+    fn is_persistent(&self) -> bool;
+    // Blocks until everything saved or removed so far is written; called on shutdown
+    fn flush(&self) {}
+}
+
+#[derive(Debug, Default)]
+pub struct InMemorySessionStore {
+    pub sessions: DashMap<String, StoredSession>,
+}
+
+// Keeps the live sessions in memory and appends every change to a log file. The file is
+// written by a background thread, so saving never waits for the disk.
+#[derive(Debug)]
+pub struct FileSessionStore {
+    pub path: String,
+    pub sessions: Arc<Mutex<HashMap<String, StoredSession>>>, // ahead of the log until it's written
+    pub writes: mpsc::Sender<StoreWrite>,
+}
+
+// Work for a file store's writer thread
+#[derive(Debug)]
+pub enum StoreWrite {
+    Entry(SessionLogEntry),
+    Flush(mpsc::Sender<()>), // answered once everything sent before it is written
+}
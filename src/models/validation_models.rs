@@ -1,3 +1,7 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::websocket_message_model::Position;
+
 pub struct ValidationService;
 
 #[derive(Debug)]
@@ -7,6 +11,61 @@ pub enum ValidationError {
     EmptyLanguage(),
     SessionIdError(String),
     InvalidCode(String),
+    MissingEntryPoint(String),
+    DisallowedPackage(String),
+}
+
+// Where execution starts, for languages whose file name depends on the code (Java).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryPoint {
+    pub file_stem: String,   // e.g. "Solution" -> Solution.java
+    pub main_symbol: String, // e.g. "com.example.Solution" for `java com.example.Solution`
+}
+
+// A problem in code, for editors: a syntax error or a disallowed import. `range` is the
+// `start`..`end` of the offending code, `None` for problems not tied to one place.
+#[derive(Debug, Clone)]
+pub struct CodeDiagnostic {
+    pub message: String,
+    pub range: Option<(Position, Position)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymbolKind {
+    Class,
+    Interface,
+    Enum,
+    Function,
+    Method,
+    Constructor,
+}
+
+// A definition in the code, for an editor's symbol sidebar. `range` is the whole definition and
+// `children` the definitions inside it, e.g. a class's methods.
+#[derive(Debug, Clone)]
+pub struct OutlineSymbol {
+    pub kind: SymbolKind,
+    pub name: String,
+    pub range: (Position, Position),
+    pub children: Vec<OutlineSymbol>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompletionKind {
+    Variable, // any other identifier in the code: variables, parameters, attributes
+    Function,
+    Class,
+    Keyword,
+    Builtin, // a standard-library name, e.g. `len` or `Math`
+}
+
+// A suggestion for the word being typed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
 }
 
 pub struct ValidRequest {
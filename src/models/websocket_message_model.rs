@@ -1,5 +1,14 @@
 use serde::{Deserialize, Serialize};
 
+use crate::models::{
+    executor_models::OutputStream,
+    in_memory_session_cache_model::SessionErrorType,
+    validation_models::{Completion, SymbolKind},
+};
+
+// Version of the response schema, sent as `version` in every response
+pub const PROTOCOL_VERSION: u32 = 1;
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum CodeType {
@@ -7,24 +16,39 @@ pub enum CodeType {
     Full,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Ordered by line, then ch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Position {
-    pub line: usize,
-    pub ch: usize,
+    pub line: usize, // 0-based
+    pub ch: usize,   // UTF-16 code units from the start of the line, as CodeMirror and Monaco count
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Replaces `start..end` with `text`. A message's patches apply in order, each to the buffer
+// the previous one left.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Patch {
     pub start: Position,
     pub end: Position,
     pub text: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// A selected range, as editors keep it: `anchor` stays put and `head` moves, either may come first
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Selection {
+    pub anchor: Position,
+    pub head: Position,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "code_type", rename_all = "lowercase")]
 pub enum Code {
-    Full { content: String },
-    Patch { patches: Vec<Patch> },
+    Full {
+        content: String,
+    },
+    Patch {
+        base_revision: u64, // revision of the buffer the patches were made against
+        patches: Vec<Patch>,
+    },
 }
 
 // PRIMARY STRUCTURE FOR WEBSOCKET MESSAGES
@@ -32,15 +56,275 @@ pub enum Code {
 pub struct WebSocketMessage {
     pub session_id: String,
     pub language: String,
+    #[serde(default)]
+    pub version: Option<String>,
     #[serde(flatten)]
     pub code: Code,
 }
 
+// Names a session, like the fields of the same name in `WebSocketMessage`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionTarget {
+    pub session_id: String,
+    pub language: String,
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+// Runs the session's current buffer; output streams back as `output` responses.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunMessage {
+    #[serde(flatten)]
+    pub target: SessionTarget,
+    #[serde(default)]
+    pub stdin: Option<String>,
+    #[serde(default)]
+    pub limits: Option<RunLimits>,
+}
+
+// Lowers the session's limits for one run; 0 (or missing) keeps the session's limit. The memory
+// limit only applies when the run starts the session's container.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunLimits {
+    #[serde(default)]
+    pub timeout_secs: u64,
+    #[serde(default)]
+    pub memory_mb: i64,
+}
+
+// What a connection that joined a session may do there. Observers only receive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParticipantRole {
+    #[default]
+    Editor,
+    Observer,
+}
+
+// Joins the session's participants, who get each other's accepted edits as `change` responses.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JoinMessage {
+    #[serde(flatten)]
+    pub target: SessionTarget,
+    #[serde(default)]
+    pub role: ParticipantRole,
+    #[serde(default)]
+    pub name: Option<String>, // shown to the other participants
+}
+
+// Where the sender's cursor and selection are in the buffer at `revision`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PresenceMessage {
+    #[serde(flatten)]
+    pub target: SessionTarget,
+    pub revision: u64,
+    #[serde(default)]
+    pub cursor: Option<Position>,
+    #[serde(default)]
+    pub selection: Option<Selection>,
+}
+
+// A session participant as the others see it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParticipantInfo {
+    pub participant: u64, // the connection's id, stable while its socket is open
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub role: ParticipantRole,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<Position>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selection: Option<Selection>,
+}
+
+// Asks for completions of the word ending at `position`, in the buffer at `revision`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompleteMessage {
+    #[serde(flatten)]
+    pub target: SessionTarget,
+    pub revision: u64,
+    pub position: Position,
+}
+
+// Anything a client sends. `type` defaults to "edit", so plain `WebSocketMessage`s still work.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ClientMessage {
+    Edit(WebSocketMessage),
+    Run(RunMessage),
+    Cancel(SessionTarget), // stops the session's run in progress
+    Resync(SessionTarget), // asks for the whole buffer, e.g. after a `conflict` error
+    Join(JoinMessage),
+    Leave(SessionTarget), // stops receiving the session's changes
+    Presence(PresenceMessage),
+    Outline(SessionTarget), // asks for the buffer's classes, functions and methods
+    Complete(CompleteMessage),
+}
+
+// Every message the server sends is one of these, e.g. `{"version": 1, "type": "ack", ...}`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebSocketResponse {
+    pub version: u32,
+    #[serde(flatten)]
+    pub body: ResponseBody,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseBody {
+    // The edit was applied; `revision` counts the changes to the session's buffer
+    Ack {
+        session_id: String,
+        language: String,
+        revision: u64,
+    },
+    // The whole buffer at `revision`, the answer to `resync`
+    Document {
+        session_id: String,
+        language: String,
+        revision: u64,
+        content: String,
+    },
+    // Answer to `join`: the role granted and the whole buffer, from which `change`s follow, and
+    // who else is there
+    Joined {
+        session_id: String,
+        language: String,
+        participant: u64,
+        role: ParticipantRole,
+        revision: u64,
+        content: String,
+        participants: Vec<ParticipantInfo>,
+    },
+    ParticipantJoined {
+        session_id: String,
+        language: String,
+        #[serde(flatten)]
+        participant: ParticipantInfo,
+    },
+    ParticipantLeft {
+        session_id: String,
+        language: String,
+        participant: u64,
+    },
+    // A participant moved its cursor or selection; positions are in the buffer at `revision`
+    Presence {
+        session_id: String,
+        language: String,
+        revision: u64,
+        #[serde(flatten)]
+        participant: ParticipantInfo,
+    },
+    // Another participant's edit, as applied; it turns revision `revision - 1` into `revision`
+    Change {
+        session_id: String,
+        language: String,
+        revision: u64,
+        #[serde(flatten)]
+        code: Code,
+    },
+    // Syntax and import check of the whole buffer at `revision`
+    Validation {
+        session_id: String,
+        language: String,
+        revision: u64,
+        valid: bool,
+        diagnostics: Vec<Diagnostic>,
+    },
+    // Answer to `outline`: the definitions in the buffer at `revision`
+    Outline {
+        session_id: String,
+        language: String,
+        revision: u64,
+        symbols: Vec<Symbol>,
+    },
+    // Answer to `complete`: suggestions, best first, to replace `start`..`end` (the word being
+    // typed) with; positions are in the buffer at `revision`
+    Completions {
+        session_id: String,
+        language: String,
+        revision: u64,
+        start: Position,
+        end: Position,
+        items: Vec<Completion>,
+    },
+    // A piece of a run's stdout or stderr, sent as the run produces it
+    Output {
+        session_id: String,
+        language: String,
+        stream: OutputStream,
+        data: String,
+    },
+    // End of a run, the WebSocket counterpart of `ExecuteResponse`. `output` repeats everything
+    // streamed before; `message` says why a run failed.
+    Execution {
+        session_id: String,
+        language: String,
+        status: RunStatus,
+        output: String,
+        runtime_version: String,
+        session_ttl_seconds: u64,
+        concurrency: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        environment_lost: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+    // The message was rejected; `code` follows HTTP status codes. `session_id` and `language`
+    // are those of the rejected message, as sent, unless it couldn't be parsed.
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session_id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        language: Option<String>,
+        error_type: SessionErrorType,
+        code: u16,
+        message: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticSeverity {
+    Error,
+}
+
+// `start`..`end` is the offending code, when the problem is tied to one place
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: DiagnosticSeverity,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<Position>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<Position>,
+}
+
+// A definition in the buffer; `start`..`end` spans all of it, `children` are the definitions
+// inside it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    pub name: String,
+    pub start: Position,
+    pub end: Position,
+    pub children: Vec<Symbol>,
+}
+
 /***
  * Example message (as JSON):
  * {
  *   "session_id": "abc123",
  *   "language": "rust",
+ *   "version": "1.78",
  *   "code_type": "full",
  *   "content": "fn main() { println!(\"Hello, world!\"); }"
  * }
@@ -50,6 +334,7 @@ pub struct WebSocketMessage {
  *   "session_id": "abc123",
  *   "language": "rust",
  *   "code_type": "patch",
+ *   "base_revision": 6,
  *   "patches": [
  *     {
  *       "start": { "line": 1, "ch": 0 },
@@ -59,3 +344,34 @@ pub struct WebSocketMessage {
  *   ]
  * }
 */
+/***
+ * Example responses (as JSON), one per line:
+ * {"version":1,"type":"ack","session_id":"abc123","language":"python@3.12","revision":7}
+ * {"version":1,"type":"validation","session_id":"abc123","language":"python@3.12","revision":7,
+ *  "valid":false,"diagnostics":[{"severity":"error","message":"Invalid code: ..."}]}
+ * {"version":1,"type":"output","session_id":"abc123","language":"python@3.12","stream":"stdout",
+ *  "data":"hello\n"}
+ * {"version":1,"type":"execution","session_id":"abc123","language":"python@3.12",
+ *  "status":"completed","output":"hello\n","runtime_version":"Python 3.12.4",
+ *  "session_ttl_seconds":3600,"concurrency":"immediate"}
+ * {"version":1,"type":"error","session_id":"abc123","language":"python","error_type":"conflict",
+ *  "code":409,"message":"..."}
+ *
+ * Running and cancelling:
+ * {"type": "run", "session_id": "abc123", "language": "python", "stdin": "42\n",
+ *  "limits": {"timeout_secs": 5}}
+ * {"type": "cancel", "session_id": "abc123", "language": "python"}
+ *
+ * Resyncing after a `conflict` error, answered with a `document` response:
+ * {"type": "resync", "session_id": "abc123", "language": "python"}
+ *
+ * Pair programming: both join, then edit as usual; each gets the other's edits as `change`s.
+ * {"type": "join", "session_id": "abc123", "language": "python", "role": "editor"}
+ * {"type": "join", "session_id": "abc123", "language": "python", "role": "observer"}
+ * {"type": "leave", "session_id": "abc123", "language": "python"}
+ *
+ * Presence, after joining (with an optional "name"): the cursor and selection at a revision.
+ * {"type": "presence", "session_id": "abc123", "language": "python", "revision": 7,
+ *  "cursor": {"line": 2, "ch": 4},
+ *  "selection": {"anchor": {"line": 2, "ch": 0}, "head": {"line": 2, "ch": 4}}}
+*/
@@ -1,31 +1,37 @@
 use dashmap::DashMap;
-use std::cmp::Reverse;
-use std::collections::BinaryHeap;
-use std::sync::{Arc, LazyLock, Mutex};
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::UnboundedSender;
+use tungstenite::Message;
 
-pub enum CleanupType {
-    Full,
-    Search { key: String },
-}
+use crate::models::{
+    session_management_models::SessionKey,
+    websocket_message_model::{ParticipantRole, Position, Selection},
+};
 
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Status {
-    pub last_active: u64,
-    pub value: String,
+// A WebSocket connection that joined a session. `cursor` and `selection` are kept at the
+// buffer's current revision.
+#[derive(Debug, Clone)]
+pub struct Participant {
+    pub role: ParticipantRole,
+    pub name: Option<String>,
+    pub cursor: Option<Position>,
+    pub selection: Option<Selection>,
+    pub responses: UnboundedSender<Message>, // the connection's writer
 }
 
-// For the heap, we need to store the key as well, so we can find/remove from the pool.
-#[derive(Eq, PartialEq, Debug)]
-pub struct ActivityTracker {
-    pub last_active: u64,
-    pub key: String,
+// The connections taking part in one session, by connection id. WebSocket edits of the session
+// are applied and broadcast one at a time, holding `sequencer`, so every participant sees the
+// changes in revision order.
+#[derive(Debug, Default)]
+pub struct SessionRoom {
+    pub sequencer: tokio::sync::Mutex<()>,
+    pub participants: Mutex<HashMap<u64, Participant>>,
 }
 
-type ConnectionPool = LazyLock<Arc<DashMap<String, Status>>>;
-
 #[derive(Debug)]
 pub struct ConnectionManager {
-    pub connection_size: u32,
-    pub pool: ConnectionPool,
-    pub activity_tracker: Mutex<BinaryHeap<Reverse<ActivityTracker>>>, // Min-heap by last_active
+    pub rooms: DashMap<SessionKey, Arc<SessionRoom>>, // only while joined or being edited
+    pub next_connection_id: AtomicU64,
 }
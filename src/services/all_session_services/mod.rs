@@ -1,3 +1,4 @@
-pub mod session_cache_service;
+pub mod session_lifecycle_service;
 pub mod session_management_service;
 pub mod session_service;
+pub mod session_store_service;
@@ -0,0 +1,200 @@
+use tonic::Status;
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        config_models::LanguageLimits,
+        session_management_models::{SessionError, SessionKey, SessionValue},
+    },
+    proto::executor::{
+        CloseSessionResponse, CreateSessionRequest, ListSessionsRequest, ListSessionsResponse,
+        SessionInfo, SessionLimits, SessionRef,
+    },
+    services::{
+        all_session_services::session_management_service::{
+            SessionManagement, SessionManagementService,
+        },
+        execution_services::executor_service::execution_error_status,
+        helper_services::{
+            config_service::get_global_config, language_registry_service::get_language_registry,
+        },
+    },
+};
+
+/// Creates an empty session with a server-generated id. Its container starts on the first
+/// `Execute`, so creating sessions up front costs nothing but a slot under `max_sessions`.
+pub async fn create_session(request: CreateSessionRequest) -> Result<SessionInfo, Status> {
+    let owner = required_owner(&request.owner).map_err(session_status)?;
+    let language = get_language_registry()
+        .resolve_version(&request.language.to_lowercase(), Some(&request.version))
+        .ok_or_else(|| {
+            Status::invalid_argument(format!("Unsupported language: {}", request.language))
+        })?;
+    let session_id = Uuid::new_v4().to_string();
+    let mut session = SessionValue::new("");
+    session.owner = Some(owner.to_string());
+    session.limits = request
+        .limits
+        .map(|limits| capped_limits(&limits, &language.config.limits));
+
+    let sessions = session_management_service().await?;
+    sessions
+        .add_session(session_id.clone(), language.key(), session)
+        .await
+        .map_err(session_status)?;
+    println!("Created session {} for {}", session_id, language.key());
+    let (key, session) = sessions
+        .find_session(&session_id, &language.key())
+        .await
+        .map_err(session_status)?;
+    Ok(session_info(&key, &session))
+}
+
+pub async fn get_session(request: SessionRef) -> Result<SessionInfo, Status> {
+    let sessions = session_management_service().await?;
+    let (key, session) = find_owned_session(sessions, &request)
+        .await
+        .map_err(session_status)?;
+    Ok(session_info(&key, &session))
+}
+
+pub async fn list_sessions(request: ListSessionsRequest) -> Result<ListSessionsResponse, Status> {
+    let owner = required_owner(&request.owner).map_err(session_status)?;
+    let sessions = session_management_service()
+        .await?
+        .list_sessions(owner)
+        .await
+        .iter()
+        .map(|(key, session)| session_info(key, session))
+        .collect();
+    Ok(ListSessionsResponse { sessions })
+}
+
+/// Pushes the session's expiry forward, as any use of the session does.
+pub async fn keep_alive(request: SessionRef) -> Result<SessionInfo, Status> {
+    let sessions = session_management_service().await?;
+    let (key, _) = find_owned_session(sessions, &request)
+        .await
+        .map_err(session_status)?;
+    let session = sessions
+        .get_session(&key.session_id, &key.language)
+        .await
+        .map_err(session_status)?;
+    Ok(session_info(&key, &session))
+}
+
+/// Ends the session now; its container and per-session image are removed in the background.
+pub async fn close_session(request: SessionRef) -> Result<CloseSessionResponse, Status> {
+    let sessions = session_management_service().await?;
+    let (key, _) = find_owned_session(sessions, &request)
+        .await
+        .map_err(session_status)?;
+    sessions
+        .delete_session(&key.to_string())
+        .await
+        .map_err(session_status)?;
+    println!("Closed session {}", key.to_string());
+    Ok(CloseSessionResponse { closed: true })
+}
+
+async fn session_management_service() -> Result<&'static SessionManagementService, Status> {
+    get_global_config(|config| config.clone())
+        .await
+        .session_management_service
+        .ok_or_else(|| Status::unavailable("Session management service not initialized"))
+}
+
+fn required_owner(owner: &str) -> Result<&str, SessionError> {
+    match owner {
+        "" => Err(SessionError::Unauthenticated(
+            "An owner is required to manage sessions.".to_string(),
+        )),
+        owner => Ok(owner),
+    }
+}
+
+// The session `request` names, if it belongs to the request's owner. Another owner's session
+// is reported as not found, so ids can't be probed.
+async fn find_owned_session(
+    sessions: &SessionManagementService,
+    request: &SessionRef,
+) -> Result<(SessionKey, SessionValue), SessionError> {
+    let owner = required_owner(&request.owner)?;
+    let language = resolve_ref_language(request)?;
+    let (key, session) = sessions
+        .find_session(&request.session_id, &language)
+        .await?;
+    if session.owner.as_deref() != Some(owner) {
+        return Err(SessionError::NotFound(request.session_id.clone()));
+    }
+    Ok((key, session))
+}
+
+// Canonical `name@version` for a `SessionRef`, or "" to match the id in any language
+fn resolve_ref_language(request: &SessionRef) -> Result<String, SessionError> {
+    if request.language.is_empty() {
+        return Ok(String::new());
+    }
+    get_language_registry()
+        .resolve_version(&request.language.to_lowercase(), Some(&request.version))
+        .map(|language| language.key())
+        .ok_or_else(|| SessionError::InvalidLanguage(request.language.clone()))
+}
+
+// Requested limits may lower the language's limits but never raise them; 0 keeps the default
+pub fn capped_limits(requested: &SessionLimits, configured: &LanguageLimits) -> LanguageLimits {
+    let timeout_secs = match requested.timeout_seconds {
+        0 => configured.timeout_secs,
+        timeout => timeout.min(configured.timeout_secs),
+    };
+    let memory_mb = match requested.memory_mb {
+        memory if memory <= 0 => configured.memory_mb,
+        memory => memory.min(configured.memory_mb),
+    };
+    LanguageLimits {
+        timeout_secs,
+        memory_mb,
+    }
+}
+
+fn session_info(key: &SessionKey, session: &SessionValue) -> SessionInfo {
+    let limits = session.limits.clone().or_else(|| {
+        get_language_registry()
+            .resolve(&key.language)
+            .map(|language| language.config.limits)
+    });
+    SessionInfo {
+        session_id: key.session_id.clone(),
+        language: key.language.clone(),
+        owner: session.owner.clone().unwrap_or_default(),
+        expires_at_unix: session.expires_at_unix() as i64,
+        ttl_seconds: session.remaining_ttl().as_secs() as i64,
+        limits: limits.map(|limits| SessionLimits {
+            timeout_seconds: limits.timeout_secs,
+            memory_mb: limits.memory_mb,
+        }),
+        has_container: session.container.is_some(),
+        runtime_version: session
+            .container
+            .as_ref()
+            .map(|container| container.runtime_version.clone())
+            .unwrap_or_default(),
+        paused: session.paused,
+        environment_lost: session.environment_lost.clone().unwrap_or_default(),
+    }
+}
+
+fn session_status(error: SessionError) -> Status {
+    match error {
+        SessionError::NotFound(_) => Status::not_found(error.message()),
+        SessionError::InvalidLanguage(_) => Status::invalid_argument(error.message()),
+        SessionError::Unauthenticated(_) => Status::unauthenticated(error.message()),
+        SessionError::ResourceExhausted(_) => execution_error_status(&error),
+        SessionError::Busy(_) => Status::aborted(error.message()),
+        SessionError::Cancelled(_) => Status::cancelled(error.message()),
+        SessionError::EnvironmentLost(_) => Status::failed_precondition(error.message()),
+        SessionError::InvalidPatch(_) => Status::invalid_argument(error.message()),
+        SessionError::StaleRevision(_, _) => Status::aborted(error.message()),
+        SessionError::ExecutionError(_) => Status::internal(error.message()),
+    }
+}
@@ -1,15 +1,32 @@
 use once_cell::sync::OnceCell;
 use std::cmp::Reverse;
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 use tonic::Request;
+use tree_sitter::Tree;
 
 use crate::{
-    models::session_management_models::{SessionError, SessionKey, SessionValue},
+    models::{
+        cleanup_models::CleanupService,
+        config_models::ConcurrentRunPolicy,
+        session_management_models::{
+            RunDisposition, RunPermit, SessionContainer, SessionError, SessionKey, SessionValue,
+        },
+        websocket_message_model::{Patch, Position},
+    },
     proto::executor::ExecuteRequest,
-    services::helper_services::config_service::get_global_config,
+    services::{
+        all_session_services::session_store_service::{StoredSession, get_session_store},
+        execution_services::code_editor_service::{CodeEditor, PatchError},
+        helper_services::{config_service::get_global_config, metrics_service::get_metrics},
+        websocket::websocket_sessionpool_service::ConnectionManager,
+    },
+    utils::docker_utils::get_docker_instance,
 };
 
 pub use crate::models::session_management_models::SessionManagementService;
@@ -26,10 +43,44 @@ impl SessionError {
             }
             SessionError::ExecutionError(msg) => format!("Execution error: {}", msg),
             SessionError::Unauthenticated(msg) => format!("Unauthenticated: {}", msg),
+            SessionError::ResourceExhausted(retry_after) => format!(
+                "All sessions are busy, retry after {} seconds.",
+                retry_after
+            ),
+            SessionError::Busy(key) => format!(
+                "Session '{}' is already running code, try again when it finishes.",
+                key
+            ),
+            SessionError::Cancelled(key) => format!("Run in session '{}' was cancelled.", key),
+            SessionError::EnvironmentLost(reason) => {
+                format!("Session environment lost: {}.", reason)
+            }
+            SessionError::InvalidPatch(msg) => format!("Invalid patch: {}", msg),
+            SessionError::StaleRevision(base, current) => format!(
+                "Patch is based on revision {} but the buffer is at revision {} and can't be transformed; resync the buffer.",
+                base, current
+            ),
         }
     }
 }
 
+impl From<PatchError> for SessionError {
+    fn from(error: PatchError) -> Self {
+        match error {
+            PatchError::Invalid(msg) => SessionError::InvalidPatch(msg),
+            PatchError::Stale { base, current } => SessionError::StaleRevision(base, current),
+        }
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Error for SessionError {}
+
 impl SessionKey {
     pub fn new(session_id: String, language: String) -> Self {
         SessionKey {
@@ -41,137 +92,608 @@ impl SessionKey {
         format!("{}:{}", self.session_id, self.language)
     }
 
+    // Session ids may contain ':', language keys don't
     pub fn from_string(s: &str) -> Option<Self> {
-        let parts: Vec<&str> = s.split(':').collect();
-        if parts.len() == 2 {
-            Some(SessionKey {
-                session_id: parts[0].to_string(),
-                language: parts[1].to_string(),
-            })
-        } else {
-            None
+        let (session_id, language) = s.rsplit_once(':')?;
+        Some(SessionKey {
+            session_id: session_id.to_string(),
+            language: language.to_string(),
+        })
+    }
+}
+
+impl SessionContainer {
+    pub fn new(
+        name: String,
+        id: String,
+        runtime_version: String,
+        image_digest: String,
+        session_image: Option<String>,
+    ) -> Self {
+        SessionContainer {
+            name,
+            id,
+            runtime_version,
+            image_digest,
+            session_image,
+        }
+    }
+}
+
+impl RunDisposition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunDisposition::Immediate => "immediate",
+            RunDisposition::Queued => "queued",
+            RunDisposition::CancelledPrevious => "cancelled_previous",
         }
     }
 }
 
 impl SessionValue {
-    pub fn new(image: String) -> Self {
-        SessionValue { image }
+    /// A session holding `code` in its buffer; its container is attached on the first run.
+    pub fn new(code: &str) -> Self {
+        SessionValue {
+            editor: CodeEditor::new(code),
+            container: None,
+            paused: false,
+            environment_lost: None,
+            owner: None,
+            limits: None,
+            expires_at: Instant::now(), // set by `add_session`
+            last_active: Instant::now(),
+            active_executions: 0,
+            run_lock: Arc::new(Mutex::new(())),
+            current_run: None,
+        }
+    }
+
+    pub fn remaining_ttl(&self) -> Duration {
+        self.expires_at.saturating_duration_since(Instant::now())
+    }
+
+    pub fn expires_at_unix(&self) -> u64 {
+        unix_now() + self.remaining_ttl().as_secs()
+    }
+
+    // A run holds the run lock or waits for it; either way it has a clone of the lock
+    fn run_pending(&self) -> bool {
+        self.run_lock.try_lock().is_err() || Arc::strong_count(&self.run_lock) > 1
+    }
+
+    fn to_stored(&self, key: &SessionKey) -> StoredSession {
+        StoredSession {
+            session_key: key.to_string(),
+            code: self.editor.to_string(),
+            revision: self.editor.revision,
+            container: self.container.clone(),
+            expires_at_unix: self.expires_at_unix(),
+            owner: self.owner.clone(),
+            limits: self.limits.clone(),
+        }
     }
 }
 
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
 #[async_trait::async_trait]
 pub trait SessionManagement {
-    async fn need_cleanup(&self) -> bool;
     async fn cleanup_expired_sessions(&self);
     async fn add_session(
         &self,
         session_id: String,
         language: String,
-        container_image: String,
+        session_value: SessionValue,
     ) -> Result<(), SessionError>;
 
     async fn delete_session(&self, session_key: &str) -> Result<(), SessionError>;
 
+    /// Replaces the session's buffer, creating the session (without a container) if needed.
+    async fn update_code(
+        &self,
+        session_id: &str,
+        language: &str,
+        code: &str,
+    ) -> Result<SessionValue, SessionError>;
+
+    /// Applies an editor's patches, made against `base_revision`, to the session's buffer,
+    /// creating the (empty) session if needed. Patches from all editors of a session are
+    /// applied one message at a time, in arrival order; stale ones are transformed, see
+    /// `CodeEditorModel::apply_patches`. Returns the session and the patches as applied.
+    async fn apply_patches(
+        &self,
+        session_id: &str,
+        language: &str,
+        base_revision: u64,
+        patches: &[Patch],
+    ) -> Result<(SessionValue, Vec<Patch>), SessionError>;
+
+    /// Maps positions an editor reported at `base_revision`, e.g. its cursor, to the buffer's
+    /// current revision. Doesn't extend the session. Returns that revision and the positions.
+    async fn map_positions(
+        &self,
+        session_id: &str,
+        language: &str,
+        base_revision: u64,
+        positions: &[Position],
+    ) -> Result<(u64, Vec<Position>), SessionError>;
+
+    /// Keeps `tree`, a parse of the buffer at `revision`, for reparsing the buffer after the
+    /// next edits. Dropped if the buffer has changed since.
+    async fn store_syntax_tree(&self, session_id: &str, language: &str, revision: u64, tree: Tree);
+
+    /// Records the container started for a session, creating the session if needed.
+    async fn attach_container(
+        &self,
+        session_id: &str,
+        language: &str,
+        container: SessionContainer,
+    ) -> Result<(), SessionError>;
+
     async fn get_session_image(
         &self,
         session_id: &str,
         language: &str,
     ) -> Result<String, SessionError>;
 
+    async fn get_session(
+        &self,
+        session_id: &str,
+        language: &str,
+    ) -> Result<SessionValue, SessionError>;
+
     fn get_session_id(&self, request: &Request<ExecuteRequest>) -> Result<String, SessionError>;
+
+    /// Looks a session up without refreshing its expiry. An empty `language` matches any
+    /// language, as long as only one session has that id.
+    async fn find_session(
+        &self,
+        session_id: &str,
+        language: &str,
+    ) -> Result<(SessionKey, SessionValue), SessionError>;
+
+    /// Sessions owned by `owner`.
+    async fn list_sessions(&self, owner: &str) -> Vec<(SessionKey, SessionValue)>;
+
+    /// Takes the session's run lock so runs never share its container at the same time. When
+    /// another run holds it, `session_configs.concurrent_run_policy` decides whether to wait,
+    /// fail with `Busy`, or cancel that run and wait for it to stop.
+    async fn acquire_run(
+        &self,
+        session_id: &str,
+        language: &str,
+    ) -> Result<RunPermit, SessionError>;
+
+    /// Cancels the session's run in progress; returns whether there was one. Runs waiting
+    /// for their turn aren't affected.
+    async fn cancel_run(&self, session_id: &str, language: &str) -> Result<bool, SessionError>;
+
+    /// Pauses the containers of sessions unused for `idle` that aren't running code.
+    async fn pause_idle_sessions(&self, idle: Duration);
+
+    /// Unpauses the session's container if it was paused while idle.
+    async fn resume_session(&self, session_id: &str, language: &str) -> Result<(), SessionError>;
+
+    /// Records that the container with id `container_id` died or was removed. The first reason
+    /// sticks; returns the key of the session that owned the container, if any. Matching on
+    /// the id, a container replaced under the same name isn't mistaken for the one that died.
+    async fn mark_environment_lost(&self, container_id: &str, reason: &str) -> Option<SessionKey>;
+
+    /// Forgets the session's container so the next run starts a new one, and returns it for
+    /// the caller to remove.
+    async fn detach_container(
+        &self,
+        session_id: &str,
+        language: &str,
+    ) -> Result<Option<SessionContainer>, SessionError>;
+
+    async fn begin_execution(&self, session_id: &str, language: &str);
+
+    async fn end_execution(&self, session_id: &str, language: &str);
 }
 
 impl SessionManagementService {
-    pub fn new() -> &'static Self {
+    pub fn new(ttl: Duration) -> &'static Self {
         if SINGLETON_SESSION_MANAGEMENT_SERVICE.get().is_some() {
             println!("Returning existing SessionManagementService instance");
             return SINGLETON_SESSION_MANAGEMENT_SERVICE.get().unwrap();
         }
-        println!("Creating default SessionManagementService");
+        println!(
+            "Creating SessionManagementService with a session TTL of {:?}",
+            ttl
+        );
         SINGLETON_SESSION_MANAGEMENT_SERVICE
-            .set(SessionManagementService::default())
+            .set(SessionManagementService::with_ttl(ttl))
             .ok();
         return SINGLETON_SESSION_MANAGEMENT_SERVICE.get().unwrap();
     }
 
-    pub async fn set_last_cleanup(&self, time: Instant) {
-        let mut last_cleanup = self.last_cleanup.lock().await;
-        *last_cleanup = time;
-    }
-    pub async fn get_last_cleanup(&self) -> Instant {
-        let last_cleanup = self.last_cleanup.lock().await;
-        *last_cleanup
-    }
-}
-
-impl Default for SessionManagementService {
-    fn default() -> Self {
-        println!("Initializing SessionManagementService");
+    fn with_ttl(ttl: Duration) -> Self {
         SessionManagementService {
             sessions: Arc::new(Mutex::new(HashMap::new())),
             expirations: Arc::new(Mutex::new(BinaryHeap::new())),
-            ttl: Duration::from_secs(3600),
+            ttl,
             last_cleanup: Arc::new(Mutex::new(Instant::now())),
+            store: get_session_store(),
         }
     }
-}
 
-#[async_trait::async_trait]
-impl SessionManagement for SessionManagementService {
-    async fn need_cleanup(&self) -> bool {
-        println!("Checking if cleanup is needed...");
+    /// Slides the session's expiry to `now + ttl` and returns the refreshed session.
+    /// The old heap entry stays behind and is discarded by `cleanup_expired_sessions`.
+    async fn touch(&self, key: &SessionKey) -> Result<SessionValue, SessionError> {
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions
+            .get_mut(key)
+            .ok_or_else(|| SessionError::NotFound(key.session_id.clone()))?;
+        session.last_active = Instant::now();
+        session.expires_at = session.last_active + self.ttl;
+        let refreshed = session.clone();
+        self.store.save(refreshed.to_stored(key));
+        {
+            let mut expirations = self.expirations.lock().await;
+            expirations.push(Reverse((refreshed.expires_at, key.to_string())));
+        }
+        Ok(refreshed)
+    }
 
-        // Lock `self.expirations` once and store the length
-        let expirations_len = {
-            let expirations = self.expirations.lock().await;
-            expirations.len()
+    /// Reloads the sessions kept by the session store and reattaches them to their
+    /// containers. Stopped containers are restarted; a session whose container is gone keeps
+    /// its buffer and gets a new container on its next run. Sessions whose TTL ran out while
+    /// the server was down are dropped and their resources removed.
+    pub async fn restore_sessions(&self) {
+        let stored = self.store.load_all();
+        if stored.is_empty() {
+            return;
+        }
+        let docker = match get_docker_instance() {
+            Ok(docker) => Some(docker),
+            Err(e) => {
+                eprintln!("Cannot reattach session containers without Docker: {}", e);
+                None
+            }
         };
+        let now = unix_now();
+        let mut restored = 0;
+        for session in stored {
+            let key = match SessionKey::from_string(&session.session_key) {
+                Some(key) if session.expires_at_unix > now => key,
+                _ => {
+                    println!("Dropping expired session {}", session.session_key);
+                    self.store.remove(&session.session_key);
+                    if let Some(container) = &session.container {
+                        CleanupService::remove_session_resources(
+                            &container.name,
+                            container.session_image.as_deref(),
+                        )
+                        .await;
+                    }
+                    continue;
+                }
+            };
+            let mut value = SessionValue::new(&session.code);
+            value.editor.revision = session.revision;
+            value.owner = session.owner;
+            value.limits = session.limits;
+            if let (Some(mut container), Some(docker)) = (session.container, &docker) {
+                let running = match docker.inspect_container(&container.name, None).await {
+                    Ok(inspected) => {
+                        container.id = inspected.id.unwrap_or_default();
+                        let state = inspected.state.unwrap_or_default();
+                        // Paused containers count as running; the next run unpauses them
+                        value.paused = state.paused == Some(true);
+                        state.running == Some(true)
+                            || docker
+                                .start_container::<String>(&container.name, None)
+                                .await
+                                .is_ok()
+                    }
+                    Err(_) => false,
+                };
+                if running {
+                    value.container = Some(container);
+                } else {
+                    println!(
+                        "Container '{}' for session {} is gone, it will be recreated on the next run",
+                        container.name, session.session_key
+                    );
+                    CleanupService::remove_session_resources(
+                        &container.name,
+                        container.session_image.as_deref(),
+                    )
+                    .await;
+                }
+            }
+            value.expires_at = Instant::now() + Duration::from_secs(session.expires_at_unix - now);
+            self.store.save(value.to_stored(&key));
+            self.expirations
+                .lock()
+                .await
+                .push(Reverse((value.expires_at, key.to_string())));
+            self.sessions.lock().await.insert(key, value);
+            get_metrics().increment("session_restored");
+            restored += 1;
+        }
+        println!("Restored {} sessions", restored);
+    }
 
-        // Access `GLOBAL_CONFIG` outside of the lock
-        let max_sessions = get_global_config(|config| config.clone())
-            .await
-            .session_configs
-            .max_sessions;
-        let session_timeout = get_global_config(|config| config.clone())
-            .await
-            .session_configs
-            .session_timeout;
+    /// Names of the containers and per-session images owned by live sessions.
+    pub async fn session_resources(&self) -> (HashSet<String>, HashSet<String>) {
+        let sessions = self.sessions.lock().await;
+        let containers = sessions
+            .values()
+            .filter_map(|s| s.container.as_ref())
+            .map(|container| container.name.clone())
+            .collect();
+        let images = sessions
+            .values()
+            .filter_map(|s| s.container.as_ref())
+            .filter_map(|container| container.session_image.clone())
+            .collect();
+        (containers, images)
+    }
 
-        println!(
-            "Current session count: {}, Max sessions: {}, Session timeout: {} seconds",
-            expirations_len, max_sessions, session_timeout
-        );
+    // `cleanup_expired_sessions` as of `now`
+    async fn cleanup_sessions_expired_at(&self, now: Instant) {
+        loop {
+            // Lock `self.expirations` and pop the top of the heap if it is due
+            let session_to_remove = {
+                let mut expirations = self.expirations.lock().await;
+                match expirations.peek() {
+                    Some(Reverse((expiration_time, _))) if *expiration_time <= now => expirations
+                        .pop()
+                        .map(|Reverse((_, session_key))| session_key),
+                    _ => None,
+                }
+            };
+
+            // If no session needs to be removed, break the loop
+            if let Some(session_key) = session_to_remove {
+                // Skip entries superseded by a later refresh (or already deleted sessions)
+                let still_expired = match SessionKey::from_string(&session_key) {
+                    Some(key) => {
+                        let sessions = self.sessions.lock().await;
+                        sessions
+                            .get(&key)
+                            .is_some_and(|session| session.expires_at <= now)
+                    }
+                    None => false,
+                };
+                if !still_expired {
+                    continue;
+                }
+                // Remove the session outside the lock
+                if let Err(e) = self.delete_session(&session_key).await {
+                    println!(
+                        "Error removing expired session {}: {}",
+                        session_key,
+                        e.message()
+                    );
+                } else {
+                    println!("Removed expired session: {}", session_key);
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    // `pause_idle_sessions`, pausing each container (by name) with `pause`
+    async fn pause_idle_sessions_with<F, Fut>(&self, idle: Duration, pause: F)
+    where
+        F: Fn(String) -> Fut,
+        Fut: std::future::Future<Output = Result<(), String>>,
+    {
+        let is_idle = |session: &SessionValue| {
+            !session.paused
+                && session.active_executions == 0
+                && session.last_active + idle <= Instant::now()
+        };
+        let idle_sessions: Vec<(SessionKey, String, Arc<Mutex<()>>)> = {
+            let sessions = self.sessions.lock().await;
+            sessions
+                .iter()
+                .filter(|(_, session)| is_idle(session))
+                .filter_map(|(key, session)| {
+                    session.container.as_ref().map(|container| {
+                        (
+                            key.clone(),
+                            container.name.clone(),
+                            session.run_lock.clone(),
+                        )
+                    })
+                })
+                .collect()
+        };
+        for (key, container, run_lock) in idle_sessions {
+            // Holding the run lock makes a run that arrives now wait, then resume the container
+            let Ok(_guard) = run_lock.try_lock_owned() else {
+                continue;
+            };
+            // The session may have been used, or its container replaced, since it was listed
+            let still_idle = self.sessions.lock().await.get(&key).is_some_and(|session| {
+                is_idle(session)
+                    && session
+                        .container
+                        .as_ref()
+                        .is_some_and(|current| current.name == container)
+            });
+            if !still_idle {
+                continue;
+            }
+            if let Err(e) = pause(container.clone()).await {
+                eprintln!("Failed to pause idle container '{}': {}", container, e);
+                continue;
+            }
+            if let Some(session) = self.sessions.lock().await.get_mut(&key) {
+                session.paused = true;
+            }
+            get_metrics().increment("session_paused");
+            println!("Paused idle session {}", key.to_string());
+        }
+    }
+
+    // `acquire_run` with the configured `concurrent_run_policy` given as `policy`
+    async fn acquire_run_under(
+        &self,
+        key: &SessionKey,
+        policy: ConcurrentRunPolicy,
+    ) -> Result<RunPermit, SessionError> {
+        let (run_lock, current_run) = {
+            let sessions = self.sessions.lock().await;
+            let session = sessions
+                .get(key)
+                .ok_or_else(|| SessionError::NotFound(key.to_string()))?;
+            (session.run_lock.clone(), session.current_run.clone())
+        };
+        let (guard, disposition) = match run_lock.clone().try_lock_owned() {
+            Ok(guard) => (guard, RunDisposition::Immediate),
+            Err(_) => match policy {
+                ConcurrentRunPolicy::Reject => {
+                    get_metrics().increment("run_rejected_busy");
+                    return Err(SessionError::Busy(key.to_string()));
+                }
+                ConcurrentRunPolicy::Queue => {
+                    get_metrics().increment("run_queued");
+                    (run_lock.lock_owned().await, RunDisposition::Queued)
+                }
+                ConcurrentRunPolicy::Cancel => {
+                    if let Some(token) = current_run {
+                        token.cancel();
+                    }
+                    get_metrics().increment("run_cancelled");
+                    (
+                        run_lock.lock_owned().await,
+                        RunDisposition::CancelledPrevious,
+                    )
+                }
+            },
+        };
+        let token = CancellationToken::new();
+        if let Some(session) = self.sessions.lock().await.get_mut(key) {
+            session.current_run = Some(token.clone());
+        }
+        Ok(RunPermit {
+            _guard: guard,
+            token,
+            disposition,
+        })
+    }
+
+    // Makes room for one more session: evicts the least-recently-used idle session when
+    // `max_sessions` is reached, or fails with `ResourceExhausted` if every session is busy.
+    // Sessions running, waiting to run or joined over the WebSocket aren't idle. The caller
+    // holds the `sessions` lock through the insert that needed the room.
+    fn make_room(
+        &self,
+        sessions: &mut HashMap<SessionKey, SessionValue>,
+        max_sessions: usize,
+        retry_after: u64,
+    ) -> Result<(), SessionError> {
+        if sessions.len() < max_sessions {
+            return Ok(());
+        }
+        let lru_idle = sessions
+            .iter()
+            .filter(|(key, session)| {
+                session.active_executions == 0
+                    && !session.run_pending()
+                    && !ConnectionManager::get_connection_manager().has_participants(key)
+            })
+            .min_by_key(|(_, session)| session.last_active)
+            .map(|(key, _)| key.clone());
+        match lru_idle.and_then(|key| sessions.remove_entry(&key)) {
+            Some((key, removed)) => {
+                println!("Evicting least recently used session: {}", key.to_string());
+                get_metrics().increment("session_evicted_lru");
+                self.discard(&key, removed);
+                Ok(())
+            }
+            None => {
+                get_metrics().increment("session_rejected_capacity");
+                Err(SessionError::ResourceExhausted(retry_after))
+            }
+        }
+    }
+
+    // Forgets a session already taken out of `sessions`: drops it from the store and removes
+    // its container (and per-session image) without holding up the caller
+    fn discard(&self, key: &SessionKey, removed: SessionValue) {
+        self.store.remove(&key.to_string());
+        if let Some(container) = removed.container {
+            tokio::spawn(async move {
+                CleanupService::remove_session_resources(
+                    &container.name,
+                    container.session_image.as_deref(),
+                )
+                .await;
+            });
+        }
 
-        // Perform the cleanup check
-        max_sessions <= expirations_len || session_timeout <= self.ttl.as_secs()
+        let svc = self.clone();
+        tokio::spawn(async move {
+            svc.set_last_cleanup(Instant::now()).await;
+        });
     }
 
+    pub async fn set_last_cleanup(&self, time: Instant) {
+        let mut last_cleanup = self.last_cleanup.lock().await;
+        *last_cleanup = time;
+    }
+    pub async fn get_last_cleanup(&self) -> Instant {
+        let last_cleanup = self.last_cleanup.lock().await;
+        *last_cleanup
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionManagement for SessionManagementService {
     async fn add_session(
         &self,
         session_id: String,
         language: String,
-        container_image: String,
+        mut session_value: SessionValue,
     ) -> Result<(), SessionError> {
         let expiration_time = Instant::now() + self.ttl;
+        session_value.expires_at = expiration_time;
 
-        let mut sessions = self.sessions.lock().await;
         let key = SessionKey::new(session_id.clone(), language.clone());
+        let session_configs = get_global_config(|config| config.clone())
+            .await
+            .session_configs;
 
-        if sessions.contains_key(&key) {
-            return Err(SessionError::ExecutionError(format!(
-                "Session already exists for ID '{}' and language '{}'",
-                session_id, language
-            )));
+        {
+            // The check, any eviction and the insert happen under one lock, so concurrent
+            // creates can't both take the last slot
+            let mut sessions = self.sessions.lock().await;
+            if sessions.contains_key(&key) {
+                return Err(SessionError::ExecutionError(format!(
+                    "Session already exists for ID '{}' and language '{}'",
+                    session_id, language
+                )));
+            }
+            if sessions.len() >= session_configs.max_sessions {
+                println!(
+                    "Session limit reached, evicting before adding {}",
+                    key.to_string()
+                );
+            }
+            self.make_room(
+                &mut sessions,
+                session_configs.max_sessions,
+                session_configs.busy_retry_after,
+            )?;
+            self.store.save(session_value.to_stored(&key));
+            sessions.insert(key.clone(), session_value);
         }
         let key_clone = key.to_string();
-        sessions.insert(key, SessionValue::new(container_image));
         {
             let mut expirations = self.expirations.lock().await;
             expirations.push(Reverse((expiration_time, key_clone)));
-        } // Spawn cleanup in a background task if needed, making it non-blocking
-        if self.need_cleanup().await {
-            println!("Cleanup needed, spawning background cleanup task...");
         }
         Ok(())
     }
@@ -185,53 +707,124 @@ impl SessionManagement for SessionManagementService {
             return Err(SessionError::NotFound(session_key.to_string()));
         }
 
-        if sessions.remove(&key).is_none() {
-            return Err(SessionError::NotFound(key.to_string()));
-        }
+        let removed = match sessions.remove(&key) {
+            Some(session) => session,
+            None => return Err(SessionError::NotFound(key.to_string())),
+        };
+        self.discard(&key, removed);
+        Ok(())
+    }
 
-        let svc = self.clone();
-        tokio::spawn(async move {
-            svc.set_last_cleanup(Instant::now()).await;
-        });
+    async fn update_code(
+        &self,
+        session_id: &str,
+        language: &str,
+        code: &str,
+    ) -> Result<SessionValue, SessionError> {
+        let key = SessionKey::new(session_id.to_string(), language.to_string());
+        let exists = {
+            let mut sessions = self.sessions.lock().await;
+            match sessions.get_mut(&key) {
+                Some(session) => {
+                    session.editor.update_from_string(code);
+                    true
+                }
+                None => false,
+            }
+        };
+        if !exists {
+            self.add_session(
+                session_id.to_string(),
+                language.to_string(),
+                SessionValue::new(code),
+            )
+            .await?;
+        }
+        self.touch(&key).await
+    }
 
-        Ok(())
+    async fn apply_patches(
+        &self,
+        session_id: &str,
+        language: &str,
+        base_revision: u64,
+        patches: &[Patch],
+    ) -> Result<(SessionValue, Vec<Patch>), SessionError> {
+        let key = SessionKey::new(session_id.to_string(), language.to_string());
+        let exists = self.sessions.lock().await.contains_key(&key);
+        if !exists {
+            self.add_session(
+                session_id.to_string(),
+                language.to_string(),
+                SessionValue::new(""),
+            )
+            .await?;
+        }
+        let applied = {
+            let mut sessions = self.sessions.lock().await;
+            let session = sessions
+                .get_mut(&key)
+                .ok_or_else(|| SessionError::NotFound(key.to_string()))?;
+            session.editor.apply_patches(base_revision, patches)?
+        };
+        Ok((self.touch(&key).await?, applied))
     }
 
-    async fn cleanup_expired_sessions(&self) {
-        let now = Instant::now();
+    async fn map_positions(
+        &self,
+        session_id: &str,
+        language: &str,
+        base_revision: u64,
+        positions: &[Position],
+    ) -> Result<(u64, Vec<Position>), SessionError> {
+        let key = SessionKey::new(session_id.to_string(), language.to_string());
+        let sessions = self.sessions.lock().await;
+        let session = sessions
+            .get(&key)
+            .ok_or_else(|| SessionError::NotFound(key.to_string()))?;
+        let mapped = session.editor.map_positions(base_revision, positions)?;
+        Ok((session.editor.revision, mapped))
+    }
 
-        loop {
-            // Lock `self.expirations` and check the top of the heap
-            let session_to_remove = {
-                let expirations = self.expirations.lock().await;
-                if let Some(Reverse((expiration_time, session_key))) = expirations.peek() {
-                    if *expiration_time <= now {
-                        // Clone the session_key to avoid borrowing issues
-                        Some(session_key.clone())
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            };
+    async fn store_syntax_tree(&self, session_id: &str, language: &str, revision: u64, tree: Tree) {
+        let key = SessionKey::new(session_id.to_string(), language.to_string());
+        let mut sessions = self.sessions.lock().await;
+        if let Some(session) = sessions.get_mut(&key)
+            && session.editor.revision == revision
+        {
+            session.editor.syntax_tree = Some(tree);
+        }
+    }
 
-            // If no session needs to be removed, break the loop
-            if let Some(session_key) = session_to_remove {
-                // Remove the session outside the lock
-                if let Err(e) = self.delete_session(&session_key).await {
-                    println!(
-                        "Error removing expired session {}: {}",
-                        session_key,
-                        e.message()
-                    );
-                } else {
-                    println!("Removed expired session: {}", session_key);
+    async fn attach_container(
+        &self,
+        session_id: &str,
+        language: &str,
+        container: SessionContainer,
+    ) -> Result<(), SessionError> {
+        let key = SessionKey::new(session_id.to_string(), language.to_string());
+        {
+            let mut sessions = self.sessions.lock().await;
+            if let Some(session) = sessions.get_mut(&key) {
+                if session.container.is_some() {
+                    return Err(SessionError::ExecutionError(format!(
+                        "Session '{}' already has a container",
+                        key.to_string()
+                    )));
                 }
-            } else {
-                break;
+                session.container = Some(container);
+                self.store.save(session.to_stored(&key));
+                return Ok(());
             }
         }
+        let mut session_value = SessionValue::new("");
+        session_value.container = Some(container);
+        self.add_session(session_id.to_string(), language.to_string(), session_value)
+            .await
+    }
+
+    async fn cleanup_expired_sessions(&self) {
+        self.cleanup_sessions_expired_at(Instant::now()).await;
     }
 
     async fn get_session_image(
@@ -239,13 +832,23 @@ impl SessionManagement for SessionManagementService {
         session_id: &str,
         language: &str,
     ) -> Result<String, SessionError> {
-        let sessions = self.sessions.lock().await;
         let key = SessionKey::new(session_id.to_string(), language.to_string());
 
-        match sessions.get(&key) {
-            Some(val) => Ok(val.image.clone()),
-            None => Err(SessionError::NotFound(session_id.to_string())),
-        }
+        self.touch(&key)
+            .await?
+            .container
+            .map(|container| container.name)
+            .ok_or_else(|| SessionError::NotFound(key.to_string()))
+    }
+
+    async fn get_session(
+        &self,
+        session_id: &str,
+        language: &str,
+    ) -> Result<SessionValue, SessionError> {
+        let key = SessionKey::new(session_id.to_string(), language.to_string());
+
+        self.touch(&key).await
     }
 
     fn get_session_id(&self, request: &Request<ExecuteRequest>) -> Result<String, SessionError> {
@@ -263,4 +866,621 @@ impl SessionManagement for SessionManagementService {
         }
         Ok(session_id)
     }
+
+    async fn find_session(
+        &self,
+        session_id: &str,
+        language: &str,
+    ) -> Result<(SessionKey, SessionValue), SessionError> {
+        let sessions = self.sessions.lock().await;
+        let mut matches = sessions.iter().filter(|(key, _)| {
+            key.session_id == session_id && (language.is_empty() || key.language == language)
+        });
+        match (matches.next(), matches.next()) {
+            (Some((key, session)), None) => Ok((key.clone(), session.clone())),
+            (None, _) => Err(SessionError::NotFound(session_id.to_string())),
+            (Some(_), Some(_)) => Err(SessionError::InvalidLanguage(format!(
+                "session '{}' exists for several languages, specify one",
+                session_id
+            ))),
+        }
+    }
+
+    async fn list_sessions(&self, owner: &str) -> Vec<(SessionKey, SessionValue)> {
+        let sessions = self.sessions.lock().await;
+        sessions
+            .iter()
+            .filter(|(_, session)| session.owner.as_deref() == Some(owner))
+            .map(|(key, session)| (key.clone(), session.clone()))
+            .collect()
+    }
+
+    async fn acquire_run(
+        &self,
+        session_id: &str,
+        language: &str,
+    ) -> Result<RunPermit, SessionError> {
+        let policy = get_global_config(|config| config.clone())
+            .await
+            .session_configs
+            .concurrent_run_policy;
+        let key = SessionKey::new(session_id.to_string(), language.to_string());
+        self.acquire_run_under(&key, policy).await
+    }
+
+    async fn cancel_run(&self, session_id: &str, language: &str) -> Result<bool, SessionError> {
+        let key = SessionKey::new(session_id.to_string(), language.to_string());
+        let sessions = self.sessions.lock().await;
+        let session = sessions
+            .get(&key)
+            .ok_or_else(|| SessionError::NotFound(key.to_string()))?;
+        // The token of a finished run stays behind; only a held run lock means a run is going
+        let running = session.run_lock.try_lock().is_err();
+        match &session.current_run {
+            Some(token) if running && !token.is_cancelled() => {
+                token.cancel();
+                get_metrics().increment("run_cancelled_by_client");
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn pause_idle_sessions(&self, idle: Duration) {
+        self.pause_idle_sessions_with(idle, |container| async move {
+            let docker = get_docker_instance().map_err(|e| e.to_string())?;
+            docker
+                .pause_container(&container)
+                .await
+                .map_err(|e| e.to_string())
+        })
+        .await;
+    }
+
+    async fn resume_session(&self, session_id: &str, language: &str) -> Result<(), SessionError> {
+        let key = SessionKey::new(session_id.to_string(), language.to_string());
+        let container = {
+            let sessions = self.sessions.lock().await;
+            let session = sessions
+                .get(&key)
+                .ok_or_else(|| SessionError::NotFound(key.to_string()))?;
+            match (&session.container, session.paused) {
+                (Some(container), true) => container.name.clone(),
+                _ => return Ok(()),
+            }
+        };
+        let started = Instant::now();
+        let docker =
+            get_docker_instance().map_err(|e| SessionError::ExecutionError(e.to_string()))?;
+        docker.unpause_container(&container).await.map_err(|e| {
+            SessionError::ExecutionError(format!(
+                "Failed to resume container '{}': {}",
+                container, e
+            ))
+        })?;
+        if let Some(session) = self.sessions.lock().await.get_mut(&key) {
+            session.paused = false;
+        }
+        get_metrics().increment("session_resumed");
+        get_metrics().observe("session_resume", started.elapsed());
+        println!(
+            "Resumed session {} in {:?}",
+            key.to_string(),
+            started.elapsed()
+        );
+        Ok(())
+    }
+
+    async fn mark_environment_lost(&self, container_id: &str, reason: &str) -> Option<SessionKey> {
+        if container_id.is_empty() {
+            return None;
+        }
+        let mut sessions = self.sessions.lock().await;
+        let (key, session) = sessions.iter_mut().find(|(_, session)| {
+            session
+                .container
+                .as_ref()
+                .is_some_and(|owned| owned.id == container_id)
+        })?;
+        if session.environment_lost.is_none() {
+            session.environment_lost = Some(reason.to_string());
+            get_metrics().increment("session_environment_lost");
+            let name = session.container.as_ref().map_or("", |owned| &owned.name);
+            println!(
+                "Session {} lost its container '{}': {}",
+                key.to_string(),
+                name,
+                reason
+            );
+        }
+        Some(key.clone())
+    }
+
+    async fn detach_container(
+        &self,
+        session_id: &str,
+        language: &str,
+    ) -> Result<Option<SessionContainer>, SessionError> {
+        let key = SessionKey::new(session_id.to_string(), language.to_string());
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions
+            .get_mut(&key)
+            .ok_or_else(|| SessionError::NotFound(key.to_string()))?;
+        let container = session.container.take();
+        session.paused = false;
+        session.environment_lost = None;
+        self.store.save(session.to_stored(&key));
+        Ok(container)
+    }
+
+    async fn begin_execution(&self, session_id: &str, language: &str) {
+        let key = SessionKey::new(session_id.to_string(), language.to_string());
+        if let Some(session) = self.sessions.lock().await.get_mut(&key) {
+            session.active_executions += 1;
+        }
+    }
+
+    async fn end_execution(&self, session_id: &str, language: &str) {
+        let key = SessionKey::new(session_id.to_string(), language.to_string());
+        if let Some(session) = self.sessions.lock().await.get_mut(&key) {
+            session.active_executions = session.active_executions.saturating_sub(1);
+            session.last_active = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::websocket_message_model::ParticipantRole,
+        services::{
+            helper_services::config_service::init_test_config,
+            websocket::websocket_sessionpool_service::Participant,
+        },
+    };
+
+    fn service() -> Arc<SessionManagementService> {
+        Arc::new(SessionManagementService::with_ttl(Duration::from_secs(60)))
+    }
+
+    #[tokio::test]
+    async fn make_room_evicts_the_least_recently_used_idle_session() {
+        let service = service();
+        let mut sessions = HashMap::new();
+        for (id, age, active_executions) in [
+            ("old", 30, 0),
+            ("busy", 60, 1),
+            ("new", 10, 0),
+            ("running", 90, 0),
+            ("waiting", 80, 0),
+            ("make-room-joined", 70, 0),
+        ] {
+            let mut session = SessionValue::new("");
+            session.last_active = Instant::now() - Duration::from_secs(age);
+            session.active_executions = active_executions;
+            sessions.insert(
+                SessionKey::new(id.to_string(), "python".to_string()),
+                session,
+            );
+        }
+        let lock_of = |id: &str| {
+            sessions[&SessionKey::new(id.to_string(), "python".to_string())]
+                .run_lock
+                .clone()
+        };
+        // A run in progress, and one that took the lock to wait for it
+        let _running = lock_of("running").try_lock_owned().unwrap();
+        let _waiting = lock_of("waiting");
+        let joined = SessionKey::new("make-room-joined".to_string(), "python".to_string());
+        let manager = ConnectionManager::get_connection_manager();
+        let connection = manager.next_connection_id();
+        manager.join(
+            &joined,
+            connection,
+            Participant {
+                role: ParticipantRole::Editor,
+                name: None,
+                cursor: None,
+                selection: None,
+                responses: tokio::sync::mpsc::unbounded_channel().0,
+            },
+        );
+
+        service.make_room(&mut sessions, 6, 5).unwrap();
+        manager.leave_all(connection);
+
+        let mut left: Vec<_> = sessions.keys().map(|key| key.session_id.as_str()).collect();
+        left.sort();
+        assert_eq!(
+            left,
+            ["busy", "make-room-joined", "new", "running", "waiting"]
+        );
+        // Below the limit nothing is evicted
+        service.make_room(&mut sessions, 6, 5).unwrap();
+        assert_eq!(sessions.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn make_room_fails_when_every_session_is_busy() {
+        let service = service();
+        let mut session = SessionValue::new("");
+        session.active_executions = 1;
+        let mut sessions = HashMap::from([(
+            SessionKey::new("busy".to_string(), "python".to_string()),
+            session,
+        )]);
+
+        match service.make_room(&mut sessions, 1, 7) {
+            Err(SessionError::ResourceExhausted(retry_after)) => assert_eq!(retry_after, 7),
+            other => panic!("expected ResourceExhausted, got {:?}", other),
+        }
+        assert_eq!(sessions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn list_sessions_only_returns_the_owners_sessions() {
+        let service = service();
+        for (id, owner) in [("a", Some("alice")), ("b", Some("bob")), ("c", None)] {
+            let mut session = SessionValue::new("");
+            session.owner = owner.map(str::to_string);
+            service.sessions.lock().await.insert(
+                SessionKey::new(id.to_string(), "python".to_string()),
+                session,
+            );
+        }
+
+        let listed = service.list_sessions("alice").await;
+        let ids: Vec<_> = listed
+            .iter()
+            .map(|(key, _)| key.session_id.as_str())
+            .collect();
+        assert_eq!(ids, ["a"]);
+        assert!(service.list_sessions("").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_replaced_container_is_not_marked_lost_for_its_predecessor() {
+        let service = service();
+        let key = SessionKey::new("a".to_string(), "python".to_string());
+        let mut session = SessionValue::new("");
+        // Recreated under the same name as the container that died
+        session.container = Some(SessionContainer::new(
+            "executor_python_a".to_string(),
+            "new-id".to_string(),
+            String::new(),
+            String::new(),
+            None,
+        ));
+        service.sessions.lock().await.insert(key.clone(), session);
+
+        assert_eq!(
+            service
+                .mark_environment_lost("old-id", "container exited")
+                .await,
+            None
+        );
+        assert_eq!(service.sessions.lock().await[&key].environment_lost, None);
+        assert_eq!(
+            service
+                .mark_environment_lost("new-id", "container exited")
+                .await,
+            Some(key.clone())
+        );
+        assert_eq!(
+            service.sessions.lock().await[&key]
+                .environment_lost
+                .as_deref(),
+            Some("container exited")
+        );
+    }
+
+    #[tokio::test]
+    async fn touched_sessions_outlive_their_first_deadline() {
+        let service = service();
+        let deadline = Instant::now();
+        for id in ["touched", "expired"] {
+            let key = SessionKey::new(id.to_string(), "python".to_string());
+            let mut session = SessionValue::new("");
+            session.expires_at = deadline;
+            service.sessions.lock().await.insert(key.clone(), session);
+            service
+                .expirations
+                .lock()
+                .await
+                .push(Reverse((deadline, key.to_string())));
+        }
+        // Deleted before its deadline
+        service
+            .expirations
+            .lock()
+            .await
+            .push(Reverse((deadline, "deleted:python".to_string())));
+        let touched = SessionKey::new("touched".to_string(), "python".to_string());
+        let refreshed = service.touch(&touched).await.unwrap();
+        assert!(refreshed.expires_at > deadline);
+
+        service.cleanup_sessions_expired_at(deadline).await;
+        let left: Vec<_> = service.sessions.lock().await.keys().cloned().collect();
+        assert_eq!(left, [touched]);
+        // Only the touched session's refreshed entry is left in the heap
+        assert_eq!(service.expirations.lock().await.len(), 1);
+
+        service
+            .cleanup_sessions_expired_at(refreshed.expires_at - Duration::from_millis(1))
+            .await;
+        assert_eq!(service.sessions.lock().await.len(), 1);
+        service
+            .cleanup_sessions_expired_at(refreshed.expires_at)
+            .await;
+        assert!(service.sessions.lock().await.is_empty());
+        assert!(service.expirations.lock().await.is_empty());
+    }
+
+    #[test]
+    fn session_keys_round_trip_through_strings() {
+        for (session_id, language) in [("abc", "python@3.12"), ("user:42:main", "java")] {
+            let key = SessionKey::new(session_id.to_string(), language.to_string());
+            assert_eq!(SessionKey::from_string(&key.to_string()), Some(key));
+        }
+        assert_eq!(SessionKey::from_string("no-language"), None);
+    }
+
+    async fn insert_session(service: &SessionManagementService, id: &str) -> SessionKey {
+        let key = SessionKey::new(id.to_string(), "python".to_string());
+        service
+            .sessions
+            .lock()
+            .await
+            .insert(key.clone(), SessionValue::new(""));
+        key
+    }
+
+    #[tokio::test]
+    async fn a_second_run_waits_for_the_first_under_queue() {
+        let service = service();
+        let key = insert_session(&service, "queue").await;
+        let first = service
+            .acquire_run_under(&key, ConcurrentRunPolicy::Queue)
+            .await
+            .unwrap();
+        assert_eq!(first.disposition, RunDisposition::Immediate);
+
+        let second = tokio::spawn({
+            let service = service.clone();
+            let key = key.clone();
+            async move {
+                service
+                    .acquire_run_under(&key, ConcurrentRunPolicy::Queue)
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!second.is_finished());
+        assert!(!first.token.is_cancelled());
+
+        drop(first);
+        let second = second.await.unwrap().unwrap();
+        assert_eq!(second.disposition, RunDisposition::Queued);
+    }
+
+    #[tokio::test]
+    async fn a_second_run_is_refused_under_reject() {
+        let service = service();
+        let key = insert_session(&service, "reject").await;
+        let first = service
+            .acquire_run_under(&key, ConcurrentRunPolicy::Reject)
+            .await
+            .unwrap();
+
+        match service
+            .acquire_run_under(&key, ConcurrentRunPolicy::Reject)
+            .await
+        {
+            Err(SessionError::Busy(busy)) => assert_eq!(busy, key.to_string()),
+            Err(other) => panic!("expected Busy, got {:?}", other),
+            Ok(_) => panic!("expected Busy, got a permit"),
+        }
+        assert!(!first.token.is_cancelled());
+        drop(first);
+        let again = service
+            .acquire_run_under(&key, ConcurrentRunPolicy::Reject)
+            .await
+            .unwrap();
+        assert_eq!(again.disposition, RunDisposition::Immediate);
+    }
+
+    #[tokio::test]
+    async fn a_second_run_cancels_the_first_under_cancel() {
+        let service = service();
+        let key = insert_session(&service, "cancel").await;
+        let first = service
+            .acquire_run_under(&key, ConcurrentRunPolicy::Cancel)
+            .await
+            .unwrap();
+
+        let second = tokio::spawn({
+            let service = service.clone();
+            let key = key.clone();
+            async move {
+                service
+                    .acquire_run_under(&key, ConcurrentRunPolicy::Cancel)
+                    .await
+            }
+        });
+        // The first run stops on its token, then lets go of the session
+        first.token.cancelled().await;
+        assert!(!second.is_finished());
+        drop(first);
+        let second = second.await.unwrap().unwrap();
+        assert_eq!(second.disposition, RunDisposition::CancelledPrevious);
+        assert!(!second.token.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn cancel_run_only_cancels_a_run_in_progress() {
+        let service = service();
+        let key = insert_session(&service, "cancel-run").await;
+        assert!(!service.cancel_run("cancel-run", "python").await.unwrap());
+
+        let run = service
+            .acquire_run_under(&key, ConcurrentRunPolicy::Queue)
+            .await
+            .unwrap();
+        assert!(service.cancel_run("cancel-run", "python").await.unwrap());
+        assert!(run.token.is_cancelled());
+
+        // A finished run's token is left behind, but there is nothing to cancel
+        drop(run);
+        assert!(!service.cancel_run("cancel-run", "python").await.unwrap());
+        assert!(matches!(
+            service.cancel_run("unknown", "python").await,
+            Err(SessionError::NotFound(_))
+        ));
+    }
+
+    // A session whose container is `executor_<id>`, with id `<id>-id`, last used `age` ago
+    async fn insert_with_container(
+        service: &SessionManagementService,
+        id: &str,
+        age: Duration,
+    ) -> SessionKey {
+        let key = SessionKey::new(id.to_string(), "python".to_string());
+        let mut session = SessionValue::new("");
+        session.last_active = Instant::now() - age;
+        session.container = Some(SessionContainer::new(
+            format!("executor_{}", id),
+            format!("{}-id", id),
+            String::new(),
+            String::new(),
+            None,
+        ));
+        service.sessions.lock().await.insert(key.clone(), session);
+        key
+    }
+
+    #[tokio::test]
+    async fn only_idle_containers_that_paused_are_marked_paused() {
+        let service = service();
+        let idle = Duration::from_secs(60);
+        let long_ago = Duration::from_secs(120);
+        for id in ["idle", "running", "failing", "already"] {
+            insert_with_container(&service, id, long_ago).await;
+        }
+        insert_with_container(&service, "recent", Duration::ZERO).await;
+        insert_session(&service, "no-container").await;
+        let mut sessions = service.sessions.lock().await;
+        sessions
+            .get_mut(&SessionKey::new(
+                "already".to_string(),
+                "python".to_string(),
+            ))
+            .unwrap()
+            .paused = true;
+        sessions
+            .get_mut(&SessionKey::new(
+                "no-container".to_string(),
+                "python".to_string(),
+            ))
+            .unwrap()
+            .last_active -= long_ago;
+        let running = sessions[&SessionKey::new("running".to_string(), "python".to_string())]
+            .run_lock
+            .clone()
+            .try_lock_owned()
+            .unwrap();
+        drop(sessions);
+
+        let paused_containers = std::sync::Mutex::new(Vec::new());
+        service
+            .pause_idle_sessions_with(idle, |container| {
+                paused_containers.lock().unwrap().push(container.clone());
+                async move {
+                    match container.as_str() {
+                        "executor_failing" => Err("cannot pause".to_string()),
+                        _ => Ok(()),
+                    }
+                }
+            })
+            .await;
+        drop(running);
+
+        let mut paused_containers = paused_containers.into_inner().unwrap();
+        paused_containers.sort();
+        assert_eq!(paused_containers, ["executor_failing", "executor_idle"]);
+        let mut paused: Vec<_> = service
+            .sessions
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, session)| session.paused)
+            .map(|(key, _)| key.session_id.clone())
+            .collect();
+        paused.sort();
+        assert_eq!(paused, ["already", "idle"]);
+
+        // Resuming a session that isn't paused needs no Docker; detaching forgets the pause
+        assert!(service.resume_session("recent", "python").await.is_ok());
+        let detached = service.detach_container("idle", "python").await.unwrap();
+        assert_eq!(detached.unwrap().name, "executor_idle");
+        let session = service.get_session("idle", "python").await.unwrap();
+        assert!(!session.paused && session.container.is_none());
+    }
+
+    #[tokio::test]
+    async fn the_first_reason_a_container_was_lost_sticks() {
+        let service = service();
+        let key = insert_with_container(&service, "lost", Duration::ZERO).await;
+        insert_session(&service, "no-container").await;
+
+        assert_eq!(
+            service.mark_environment_lost("", "container exited").await,
+            None
+        );
+        assert_eq!(
+            service
+                .mark_environment_lost("lost-id", "out of memory")
+                .await,
+            Some(key.clone())
+        );
+        assert_eq!(
+            service
+                .mark_environment_lost("lost-id", "container was removed")
+                .await,
+            Some(key.clone())
+        );
+        let session = service.get_session("lost", "python").await.unwrap();
+        assert_eq!(session.environment_lost.as_deref(), Some("out of memory"));
+
+        // The next container starts with a clean slate
+        service.detach_container("lost", "python").await.unwrap();
+        let session = service.get_session("lost", "python").await.unwrap();
+        assert_eq!(session.environment_lost, None);
+        assert_eq!(service.mark_environment_lost("lost-id", "gone").await, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_creates_never_exceed_max_sessions() {
+        init_test_config();
+        let max_sessions = get_global_config(|config| config.session_configs.max_sessions).await;
+        let service = service();
+        let creators = max_sessions * 3;
+        let start = Arc::new(tokio::sync::Barrier::new(creators));
+        let creates: Vec<_> = (0..creators)
+            .map(|id| {
+                let service = service.clone();
+                let start = start.clone();
+                tokio::spawn(async move {
+                    start.wait().await;
+                    service
+                        .add_session(id.to_string(), "python".to_string(), SessionValue::new(""))
+                        .await
+                })
+            })
+            .collect();
+        for create in creates {
+            create.await.unwrap().unwrap();
+        }
+        assert_eq!(service.sessions.lock().await.len(), max_sessions);
+    }
 }
@@ -1,110 +1,156 @@
 use crate::{
+    models::websocket_message_model::{ResponseBody, WebSocketResponse},
     models::{
-        docker_models::DockerSupportedLanguage,
-        in_memory_session_cache_model::{Session, SessionCache, SessionError, SessionErrorType},
+        in_memory_session_cache_model::{SessionError, SessionErrorType},
+        session_management_models::{self, SessionKey, SessionValue},
     },
     services::{
-        all_session_services::session_cache_service::SessionCache as session_cache_service,
-        execution_services::code_editor_service::CodeEditor,
-        websocket::websocket_message_service::{Code, Patch, WebSocketMessage},
+        all_session_services::session_management_service::SessionManagement,
+        helper_services::config_service::get_global_config,
+        websocket::{
+            websocket_message_service::{Code, WebSocketMessage},
+            websocket_sessionpool_service::{ConnectionManager, NO_CONNECTION},
+        },
     },
 };
 
-impl Session {
-    pub fn new(
-        session_id: String,
-        language: DockerSupportedLanguage,
-        initial_code: String,
-    ) -> Self {
-        Self {
-            session_id,
-            language,
-            editor: CodeEditor::new(&initial_code),
+/// Applies a WebSocket edit to the session it names, creating the session on first use.
+/// This is the same session gRPC executes, so a run picks up the edited buffer. Also returns
+/// the change as applied, for the session's other participants, or `None` if nothing changed.
+pub async fn update_create_session(
+    message: &WebSocketMessage,
+) -> Result<(SessionKey, SessionValue, Option<Code>), SessionError> {
+    let session_key = message.session_key().map_err(|_| {
+        SessionError::new(
+            "Invalid language specified".to_string(),
+            SessionErrorType::InvalidInput,
+            400,
+        )
+    })?;
+    let session_management_service = get_global_config(|config| config.clone())
+        .await
+        .session_management_service
+        .ok_or_else(|| {
+            SessionError::new(
+                "Session management service not initialized".to_string(),
+                SessionErrorType::InternalError,
+                500,
+            )
+        })?;
+    let (session, change) = match &message.code {
+        Code::Full { content } => {
+            let session = session_management_service
+                .update_code(&session_key.session_id, &session_key.language, content)
+                .await
+                .map_err(SessionError::from)?;
+            let change = full_change(&session, content);
+            (session, change)
         }
-    }
-
-    pub fn apply_full_code(&mut self, content: &str) {
-        self.editor.update_from_string(content);
-    }
-
-    pub fn get_code(&self) -> String {
-        self.editor.to_string()
-    }
-}
-
-pub fn get_session_management_service() -> &'static SessionCache {
-    session_cache_service::new()
+        Code::Patch {
+            base_revision,
+            patches,
+        } => {
+            let (session, applied) = session_management_service
+                .apply_patches(
+                    &session_key.session_id,
+                    &session_key.language,
+                    *base_revision,
+                    patches,
+                )
+                .await
+                .map_err(SessionError::from)?;
+            let change = (!applied.is_empty()).then(|| Code::Patch {
+                base_revision: session.editor.revision - 1,
+                patches: applied,
+            });
+            (session, change)
+        }
+    };
+    Ok((session_key, session, change))
 }
 
-pub fn update_create_session(message: &WebSocketMessage) -> Result<Session, SessionError> {
-    let language_session_id = message.generate_session_id();
-    if language_session_id.is_err() {
-        return Err(SessionError::new(
-            "Failed to generate session ID".to_string(),
-            SessionErrorType::InvalidInput,
-            400,
-        ));
-    }
-    let session_management_service = get_session_management_service();
-    let session = session_management_service
-        .get_session_mut(language_session_id.as_ref().unwrap())
-        .map(|mut mut_ref| {
-            // Session exists
-            match &message.code {
-                Code::Patch { patches } => {
-                    // Get existing code
-                    let mut code_string = mut_ref.get_code();
-                    // Apply each patch (you'll need to implement apply_patch)
-                    for patch in patches {
-                        code_string = apply_patch(&code_string, patch);
-                    }
-                    // Update session code
-                    mut_ref.apply_full_code(&code_string);
-                }
-                Code::Full { content } => {
-                    // Replace code with full content
-                    mut_ref.apply_full_code(content);
-                }
-            }
-            mut_ref.clone()
-        });
-    if session.is_none() {
-        // Session does not exist, create new
-        let language = message.get_language();
-        if language.is_err() {
-            return Err(SessionError::new(
-                "Invalid language specified".to_string(),
-                SessionErrorType::InvalidInput,
-                400,
-            ));
+/// The session, created empty if it doesn't exist yet.
+pub async fn open_session(
+    key: &SessionKey,
+) -> Result<SessionValue, session_management_models::SessionError> {
+    let session_management_service = get_global_config(|config| config.clone())
+        .await
+        .session_management_service
+        .ok_or_else(|| {
+            session_management_models::SessionError::ExecutionError(
+                "Session management service not initialized".to_string(),
+            )
+        })?;
+    match session_management_service
+        .get_session(&key.session_id, &key.language)
+        .await
+    {
+        Err(session_management_models::SessionError::NotFound(_)) => {
+            session_management_service
+                .add_session(
+                    key.session_id.clone(),
+                    key.language.clone(),
+                    SessionValue::new(""),
+                )
+                .await?;
+            session_management_service
+                .get_session(&key.session_id, &key.language)
+                .await
         }
-        let new_session = Session::new(
-            language_session_id.unwrap(),
-            language.unwrap(),
-            message.get_code_string(),
-        );
-        session_management_service.insert_session(new_session.clone());
-        return Ok(new_session);
+        session => session,
     }
-    Ok(session.unwrap())
 }
 
-fn apply_patch(code: &str, patch: &Patch) -> String {
-    // This is a naive implementation. You may need to handle multi-line and character positions more robustly.
-    let mut lines: Vec<String> = code.lines().map(|l| l.to_string()).collect();
-    let Patch { start, end, text } = patch;
-    if start.line == end.line {
-        // Replace text in a single line
-        if let Some(line) = lines.get_mut(start.line) {
-            let before = &line[..start.ch];
-            let after = &line[end.ch..];
-            *line = format!("{}{}{}", before, text, after);
+/// Replaces the session's buffer with `code`, as gRPC `Execute` with code does, creating the
+/// session on first use. Goes through the session's room like a WebSocket edit, so the
+/// participants get the new buffer as a `change` in revision order.
+pub async fn replace_code(
+    key: &SessionKey,
+    code: &str,
+) -> Result<SessionValue, session_management_models::SessionError> {
+    let session_management_service = get_global_config(|config| config.clone())
+        .await
+        .session_management_service
+        .ok_or_else(|| {
+            session_management_models::SessionError::ExecutionError(
+                "Session management service not initialized".to_string(),
+            )
+        })?;
+    let manager = ConnectionManager::get_connection_manager();
+    let room = manager.room(key);
+    let updated = {
+        let _turn = room.sequencer.lock().await;
+        let updated = session_management_service
+            .update_code(&key.session_id, &key.language, code)
+            .await;
+        if let Ok(session) = &updated
+            && let Some(change) = full_change(session, code)
+        {
+            room.map_presence(NO_CONNECTION, &change);
+            let body = ResponseBody::Change {
+                session_id: key.session_id.clone(),
+                language: key.language.clone(),
+                revision: session.editor.revision,
+                code: change,
+            };
+            room.broadcast(NO_CONNECTION, &WebSocketResponse::new(body).to_json());
         }
-    } else {
-        // Multi-line patching (not implemented here)
-        // You should implement this as per your requirements
-    }
-    lines.join("\n")
+        updated
+    };
+    drop(room);
+    manager.release_room(key);
+    updated
+}
+
+// The change a full replacement with `content` made, `None` if it was a no-op
+fn full_change(session: &SessionValue, content: &str) -> Option<Code> {
+    // The buffer's latest change is a full replacement, this one unless it was a no-op
+    let replaced = session.editor.history.back().is_some_and(|record| {
+        record.revision == session.editor.revision && record.patches.is_none()
+    });
+    replaced.then(|| Code::Full {
+        content: content.to_string(),
+    })
 }
 
 impl SessionErrorType {
@@ -112,11 +158,38 @@ impl SessionErrorType {
         match self {
             SessionErrorType::NotFound => "Session not found".to_string(),
             SessionErrorType::InvalidInput => "Invalid input provided".to_string(),
+            SessionErrorType::Conflict => "Edit conflicts with the current buffer".to_string(),
+            SessionErrorType::Forbidden => "Not allowed for this participant".to_string(),
             SessionErrorType::InternalError => "Internal server error".to_string(),
         }
     }
 }
 
+impl From<session_management_models::SessionError> for SessionError {
+    fn from(error: session_management_models::SessionError) -> Self {
+        let (error_type, error_code) = match error {
+            session_management_models::SessionError::NotFound(_) => {
+                (SessionErrorType::NotFound, 404)
+            }
+            session_management_models::SessionError::ResourceExhausted(_) => {
+                (SessionErrorType::InternalError, 503)
+            }
+            session_management_models::SessionError::EnvironmentLost(_) => {
+                (SessionErrorType::InternalError, 410)
+            }
+            session_management_models::SessionError::InvalidLanguage(_)
+            | session_management_models::SessionError::InvalidPatch(_) => {
+                (SessionErrorType::InvalidInput, 400)
+            }
+            session_management_models::SessionError::StaleRevision(_, _) => {
+                (SessionErrorType::Conflict, 409)
+            }
+            _ => (SessionErrorType::InternalError, 500),
+        };
+        SessionError::new(error.message(), error_type, error_code)
+    }
+}
+
 impl SessionError {
     pub fn new(message: String, error_type: SessionErrorType, error_code: u16) -> Self {
         Self {
@@ -0,0 +1,404 @@
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub use crate::models::session_store_models::{
+    FileSessionStore, InMemorySessionStore, SessionStore, StoredSession,
+};
+use crate::models::{
+    config_models::{SessionStoreBackend, SessionStoreConfig},
+    session_store_models::{SessionLogEntry, StoreWrite},
+};
+
+// Rewrite the log once it holds this many entries more than there are live sessions
+const COMPACTION_THRESHOLD: usize = 1000;
+// Changes to one session within this window are written as a single entry, so typing in the
+// editor doesn't write the whole buffer on every keystroke
+const WRITE_DELAY: Duration = Duration::from_millis(200);
+
+static SESSION_STORE: OnceCell<Box<dyn SessionStore>> = OnceCell::new();
+
+/// Creates the configured store; falls back to memory if the file store can't be opened.
+pub fn init_session_store(config: &SessionStoreConfig) -> &'static dyn SessionStore {
+    SESSION_STORE
+        .get_or_init(|| match config.backend {
+            SessionStoreBackend::Memory => Box::new(InMemorySessionStore::default()),
+            SessionStoreBackend::File => match FileSessionStore::open(&config.path) {
+                Ok(store) => Box::new(store),
+                Err(e) => {
+                    eprintln!(
+                        "Cannot open session store '{}', keeping sessions in memory: {}",
+                        config.path, e
+                    );
+                    Box::new(InMemorySessionStore::default())
+                }
+            },
+        })
+        .as_ref()
+}
+
+pub fn get_session_store() -> &'static dyn SessionStore {
+    SESSION_STORE
+        .get_or_init(|| Box::new(InMemorySessionStore::default()))
+        .as_ref()
+}
+
+impl SessionStore for InMemorySessionStore {
+    fn save(&self, session: StoredSession) {
+        self.sessions.insert(session.session_key.clone(), session);
+    }
+
+    fn remove(&self, session_key: &str) {
+        self.sessions.remove(session_key);
+    }
+
+    fn load_all(&self) -> Vec<StoredSession> {
+        self.sessions
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect()
+    }
+
+    fn is_persistent(&self) -> bool {
+        false
+    }
+}
+
+impl FileSessionStore {
+    /// Replays the log at `path` (created if missing), compacts it to the live sessions and
+    /// starts the thread that appends to it.
+    pub fn open(path: &str) -> std::io::Result<Self> {
+        Self::open_with(path, COMPACTION_THRESHOLD, WRITE_DELAY)
+    }
+
+    fn open_with(
+        path: &str,
+        compaction_threshold: usize,
+        write_delay: Duration,
+    ) -> std::io::Result<Self> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut sessions = HashMap::new();
+        if Path::new(path).exists() {
+            let reader = BufReader::new(File::open(path)?);
+            for (number, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // A crash mid-write leaves a truncated last line; skip it and keep the rest
+                match serde_json::from_str::<SessionLogEntry>(&line) {
+                    Ok(SessionLogEntry::Put { session }) => {
+                        sessions.insert(session.session_key.clone(), *session);
+                    }
+                    Ok(SessionLogEntry::Delete { key }) => {
+                        sessions.remove(&key);
+                    }
+                    Err(e) => eprintln!(
+                        "Skipping unreadable session log line {} in '{}': {}",
+                        number + 1,
+                        path,
+                        e
+                    ),
+                }
+            }
+        }
+        let log = compact(path, &sessions)?;
+        println!(
+            "Loaded {} sessions from session store '{}'",
+            sessions.len(),
+            path
+        );
+        let sessions = Arc::new(Mutex::new(sessions));
+        let (writes, pending) = mpsc::channel();
+        let writer = LogWriter {
+            path: path.to_string(),
+            log,
+            appended: 0,
+            compaction_threshold,
+            write_delay,
+            sessions: sessions.clone(),
+        };
+        thread::Builder::new()
+            .name("session-store-writer".to_string())
+            .spawn(move || writer.run(pending))?;
+        Ok(FileSessionStore {
+            path: path.to_string(),
+            sessions,
+            writes,
+        })
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, HashMap<String, StoredSession>> {
+        match self.sessions.lock() {
+            Ok(sessions) => sessions,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn send(&self, write: StoreWrite) {
+        if self.writes.send(write).is_err() {
+            eprintln!("Session store writer for '{}' has stopped", self.path);
+        }
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    fn save(&self, session: StoredSession) {
+        self.sessions()
+            .insert(session.session_key.clone(), session.clone());
+        self.send(StoreWrite::Entry(SessionLogEntry::Put {
+            session: Box::new(session),
+        }));
+    }
+
+    fn remove(&self, session_key: &str) {
+        if self.sessions().remove(session_key).is_some() {
+            self.send(StoreWrite::Entry(SessionLogEntry::Delete {
+                key: session_key.to_string(),
+            }));
+        }
+    }
+
+    fn load_all(&self) -> Vec<StoredSession> {
+        self.sessions().values().cloned().collect()
+    }
+
+    fn is_persistent(&self) -> bool {
+        true
+    }
+
+    fn flush(&self) {
+        let (done, written) = mpsc::channel();
+        self.send(StoreWrite::Flush(done));
+        // Fails only if the writer has stopped, and then there is nothing left to wait for
+        let _ = written.recv();
+    }
+}
+
+// Owns the log file of a `FileSessionStore` and is the only one writing to it
+struct LogWriter {
+    path: String,
+    log: File,
+    appended: usize, // entries written since the last compaction
+    compaction_threshold: usize,
+    write_delay: Duration,
+    sessions: Arc<Mutex<HashMap<String, StoredSession>>>,
+}
+
+impl LogWriter {
+    fn run(mut self, writes: mpsc::Receiver<StoreWrite>) {
+        while let Ok(first) = writes.recv() {
+            // Latest entry per session; older ones would be overwritten on replay anyway
+            let mut entries = HashMap::new();
+            let mut flushes = Vec::new();
+            let mut next = Some(first);
+            let deadline = Instant::now() + self.write_delay;
+            while let Some(write) = next.take() {
+                match write {
+                    StoreWrite::Entry(entry) => {
+                        let key = match &entry {
+                            SessionLogEntry::Put { session } => session.session_key.clone(),
+                            SessionLogEntry::Delete { key } => key.clone(),
+                        };
+                        entries.insert(key, entry);
+                    }
+                    StoreWrite::Flush(done) => flushes.push(done),
+                }
+                if flushes.is_empty() {
+                    let wait = deadline.saturating_duration_since(Instant::now());
+                    next = writes.recv_timeout(wait).ok();
+                }
+            }
+            self.append(entries.into_values());
+            for done in flushes {
+                let _ = done.send(());
+            }
+        }
+    }
+
+    fn append(&mut self, entries: impl Iterator<Item = SessionLogEntry>) {
+        let mut lines = String::new();
+        let mut count = 0;
+        for entry in entries {
+            match serde_json::to_string(&entry) {
+                Ok(line) => {
+                    lines.push_str(&line);
+                    lines.push('\n');
+                    count += 1;
+                }
+                Err(e) => eprintln!("Cannot serialize session log entry: {}", e),
+            }
+        }
+        if let Err(e) = self.log.write_all(lines.as_bytes()) {
+            eprintln!("Cannot write to session store '{}': {}", self.path, e);
+            return;
+        }
+        self.appended += count;
+        let live = match self.sessions.lock() {
+            Ok(sessions) => sessions.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        if self.appended >= live.len() + self.compaction_threshold {
+            // The in-memory sessions are at least as new as everything written so far
+            match compact(&self.path, &live) {
+                Ok(log) => {
+                    self.log = log;
+                    self.appended = 0;
+                }
+                Err(e) => eprintln!("Cannot compact session store '{}': {}", self.path, e),
+            }
+        }
+    }
+}
+
+/// Rewrites the log with one `put` per live session and returns it opened for appending.
+fn compact(path: &str, sessions: &HashMap<String, StoredSession>) -> std::io::Result<File> {
+    let tmp_path = format!("{}.tmp", path);
+    {
+        let mut tmp = File::create(&tmp_path)?;
+        for session in sessions.values() {
+            let entry = SessionLogEntry::Put {
+                session: Box::new(session.clone()),
+            };
+            writeln!(tmp, "{}", serde_json::to_string(&entry)?)?;
+        }
+        tmp.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    OpenOptions::new().append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(key: &str, revision: u64) -> StoredSession {
+        StoredSession {
+            session_key: key.to_string(),
+            code: format!("print({})", revision),
+            revision,
+            container: None,
+            expires_at_unix: 0,
+            owner: None,
+            limits: None,
+        }
+    }
+
+    fn revisions(store: &FileSessionStore) -> HashMap<String, u64> {
+        store
+            .load_all()
+            .into_iter()
+            .map(|session| (session.session_key, session.revision))
+            .collect()
+    }
+
+    fn log_lines(path: &str) -> usize {
+        fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    #[test]
+    fn reopening_replays_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sessions.log");
+        let path = path.to_str().unwrap();
+        let store = FileSessionStore::open(path).unwrap();
+        store.save(session("a:python", 1));
+        store.save(session("b:java", 1));
+        store.save(session("a:python", 2));
+        store.flush();
+
+        let reopened = FileSessionStore::open(path).unwrap();
+        let expected = HashMap::from([("a:python".to_string(), 2), ("b:java".to_string(), 1)]);
+        assert_eq!(revisions(&reopened), expected);
+    }
+
+    #[test]
+    fn deleted_sessions_stay_deleted_after_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sessions.log");
+        let path = path.to_str().unwrap();
+        let store = FileSessionStore::open(path).unwrap();
+        store.save(session("a:python", 1));
+        store.save(session("b:java", 1));
+        store.flush();
+        store.remove("a:python");
+        store.flush();
+
+        let reopened = FileSessionStore::open(path).unwrap();
+        assert_eq!(
+            revisions(&reopened),
+            HashMap::from([("b:java".to_string(), 1)])
+        );
+    }
+
+    #[test]
+    fn a_truncated_last_line_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sessions.log");
+        let path = path.to_str().unwrap();
+        let store = FileSessionStore::open(path).unwrap();
+        store.save(session("a:python", 1));
+        store.flush();
+        let mut log = OpenOptions::new().append(true).open(path).unwrap();
+        write!(log, "{{\"op\":\"put\",\"session\":{{\"session_k").unwrap();
+
+        let reopened = FileSessionStore::open(path).unwrap();
+        assert_eq!(
+            revisions(&reopened),
+            HashMap::from([("a:python".to_string(), 1)])
+        );
+    }
+
+    #[test]
+    fn changes_within_the_write_delay_are_coalesced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sessions.log");
+        let path = path.to_str().unwrap();
+        let store =
+            FileSessionStore::open_with(path, COMPACTION_THRESHOLD, Duration::from_secs(60))
+                .unwrap();
+        for revision in 1..=100 {
+            store.save(session("a:python", revision));
+        }
+        store.flush();
+
+        assert_eq!(log_lines(path), 1);
+        let reopened = FileSessionStore::open(path).unwrap();
+        assert_eq!(
+            revisions(&reopened),
+            HashMap::from([("a:python".to_string(), 100)])
+        );
+    }
+
+    #[test]
+    fn the_log_is_compacted_once_it_outgrows_the_live_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sessions.log");
+        let path = path.to_str().unwrap();
+        let threshold = 10;
+        let store = FileSessionStore::open_with(path, threshold, Duration::ZERO).unwrap();
+        store.save(session("b:java", 1));
+        store.save(session("c:javascript", 1));
+        store.flush();
+        for revision in 1..=100 {
+            store.save(session("a:python", revision));
+            // One write per revision, otherwise they'd be coalesced
+            store.flush();
+            // The 3 compacted sessions plus fewer than 3 + threshold entries appended since
+            assert!(log_lines(path) < 2 * 3 + threshold);
+        }
+        store.remove("c:javascript");
+        store.flush();
+
+        let reopened = FileSessionStore::open(path).unwrap();
+        let expected = HashMap::from([("a:python".to_string(), 100), ("b:java".to_string(), 1)]);
+        assert_eq!(revisions(&reopened), expected);
+    }
+}
@@ -1,19 +1,871 @@
-use crate::models::code_editor_models::CodeEditorModel;
+use std::collections::VecDeque;
+use tree_sitter::{InputEdit, Point};
+
+pub use crate::models::code_editor_models::{CodeEditorModel, EditRecord, PatchError};
+use crate::models::websocket_message_model::{Patch, Position};
 
 // Optionally, add a type alias if you want to refer to CodeEditorModel as CodeEditor
 pub type CodeEditor = CodeEditorModel;
 
+// Changes kept for transforming stale patches; a patch based on an older revision is rejected
+const HISTORY_LIMIT: usize = 200;
+
 impl CodeEditorModel {
     pub fn new(code: &str) -> Self {
-        let lines = code.lines().map(|s| s.to_string()).collect();
-        Self { lines }
+        Self {
+            lines: split_lines(code),
+            revision: 0,
+            history: VecDeque::new(),
+            syntax_tree: None,
+        }
     }
 
     pub fn update_from_string(&mut self, content: &str) {
-        self.lines = content.lines().map(String::from).collect();
+        let lines = split_lines(content);
+        if lines != self.lines {
+            self.lines = lines;
+            self.syntax_tree = None;
+            self.record(None);
+        }
+    }
+
+    /// Applies `patches`, made against revision `base_revision`, as one change. Patches based on
+    /// an older revision are first transformed through the changes made since, so concurrent
+    /// edits from several editors all land, unless they overlap text a concurrent change
+    /// replaced. Returns the patches as applied, none if they didn't change the buffer; on error
+    /// the buffer is left as it was.
+    pub fn apply_patches(
+        &mut self,
+        base_revision: u64,
+        patches: &[Patch],
+    ) -> Result<Vec<Patch>, PatchError> {
+        if base_revision > self.revision {
+            return Err(PatchError::Invalid(format!(
+                "base revision {} is ahead of the buffer's revision {}",
+                base_revision, self.revision
+            )));
+        }
+        let mut concurrent = self.changes_since(base_revision)?;
+        let mut code = self.to_string();
+        let mut applied = Vec::with_capacity(patches.len());
+        let mut edits = Vec::with_capacity(patches.len());
+        for (index, patch) in patches.iter().enumerate() {
+            let mut patch = patch.clone();
+            // Inclusion transform: move the patch past each concurrent change, and those changes
+            // past the patch for the patches that follow it
+            for change in concurrent.iter_mut() {
+                if patch.overlaps(change) {
+                    return Err(PatchError::Stale {
+                        base: base_revision,
+                        current: self.revision,
+                    });
+                }
+                let transformed = patch.transformed(change, true);
+                *change = change.transformed(&patch, false);
+                patch = transformed;
+            }
+            let (patched, edit) = apply_patch(&code, &patch)
+                .map_err(|e| PatchError::Invalid(format!("{} (patch {})", e, index)))?;
+            code = patched;
+            edits.push(edit);
+            applied.push(patch);
+        }
+        if code == self.to_string() {
+            return Ok(Vec::new());
+        }
+        if let Some(tree) = self.syntax_tree.as_mut() {
+            for edit in &edits {
+                tree.edit(edit);
+            }
+        }
+        self.lines = split_lines(&code);
+        self.record(Some(applied.clone()));
+        Ok(applied)
+    }
+
+    /// Maps positions a client reported at `base_revision` to the current revision, as
+    /// `apply_patches` would move them; fails like it for a base it can't map from, or for a
+    /// position outside the buffer.
+    pub fn map_positions(
+        &self,
+        base_revision: u64,
+        positions: &[Position],
+    ) -> Result<Vec<Position>, PatchError> {
+        if base_revision > self.revision {
+            return Err(PatchError::Invalid(format!(
+                "base revision {} is ahead of the buffer's revision {}",
+                base_revision, self.revision
+            )));
+        }
+        let changes = self.changes_since(base_revision)?;
+        let code = self.to_string();
+        positions
+            .iter()
+            .map(|position| {
+                let mapped = changes.iter().fold(*position, |position, change| {
+                    change.map_position(position, false)
+                });
+                byte_offset(&code, &mapped)
+                    .map(|_| mapped)
+                    .map_err(PatchError::Invalid)
+            })
+            .collect()
+    }
+
+    /// Byte offset of `position` in the buffer.
+    pub fn offset_of(&self, position: &Position) -> Result<usize, PatchError> {
+        byte_offset(&self.to_string(), position).map_err(PatchError::Invalid)
     }
 
     pub fn to_string(&self) -> String {
         self.lines.join("\n")
     }
+
+    // The patches applied after `base_revision`, in order
+    fn changes_since(&self, base_revision: u64) -> Result<Vec<Patch>, PatchError> {
+        let stale = || PatchError::Stale {
+            base: base_revision,
+            current: self.revision,
+        };
+        if base_revision == self.revision {
+            return Ok(Vec::new());
+        }
+        // The history has to reach back to the change right after `base_revision`
+        let first = self
+            .history
+            .iter()
+            .position(|record| record.revision == base_revision + 1)
+            .ok_or_else(stale)?;
+        let mut changes = Vec::new();
+        for record in self.history.iter().skip(first) {
+            match &record.patches {
+                Some(patches) => changes.extend(patches.iter().cloned()),
+                // A full replacement can't be transformed through
+                None => return Err(stale()),
+            }
+        }
+        Ok(changes)
+    }
+
+    fn record(&mut self, patches: Option<Vec<Patch>>) {
+        self.revision += 1;
+        self.history.push_back(EditRecord {
+            revision: self.revision,
+            patches,
+        });
+        if self.history.len() > HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+    }
+}
+
+impl Patch {
+    /// This patch as it applies after `other` was applied to the same buffer; the two must not
+    /// overlap. Where both insert at the same position, `after` puts this patch's text after
+    /// `other`'s. Text inserted at either end of a replaced range stays outside it.
+    pub fn transformed(&self, other: &Patch, after: bool) -> Patch {
+        let replaces = self.start < self.end;
+        let start = other.map_position(self.start, after || replaces);
+        let end = other.map_position(self.end, after && !replaces);
+        Patch {
+            start,
+            end: end.max(start),
+            text: self.text.clone(),
+        }
+    }
+
+    /// Whether the two patches touch the same text, i.e. one replaces text the other replaces
+    /// or inserts into. Such concurrent patches aren't transformed.
+    pub fn overlaps(&self, other: &Patch) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+
+    /// Where `position` ends up once this patch is applied. Positions inside the replaced range
+    /// move to the end of the inserted text; one at an insertion point moves past the inserted
+    /// text only if `after`.
+    pub fn map_position(&self, position: Position, after: bool) -> Position {
+        let before_range =
+            position < self.start || (position == self.start && (self.start < self.end || !after));
+        if before_range {
+            return position;
+        }
+        let inserted_end = self.inserted_end();
+        if position <= self.end {
+            return inserted_end;
+        }
+        if position.line == self.end.line {
+            Position {
+                line: inserted_end.line,
+                ch: inserted_end.ch + position.ch - self.end.ch,
+            }
+        } else {
+            Position {
+                line: position.line - self.end.line + inserted_end.line,
+                ch: position.ch,
+            }
+        }
+    }
+
+    // Position right after the inserted text, once the patch is applied
+    fn inserted_end(&self) -> Position {
+        let newlines = self.text.matches('\n').count();
+        let last_line = self.text.rsplit('\n').next().unwrap_or("");
+        let units = last_line.encode_utf16().count();
+        if newlines == 0 {
+            Position {
+                line: self.start.line,
+                ch: self.start.ch + units,
+            }
+        } else {
+            Position {
+                line: self.start.line + newlines,
+                ch: units,
+            }
+        }
+    }
+}
+
+// Splits on '\n' only, unlike `str::lines`, so a trailing newline and any '\r' survive
+// `to_string`
+fn split_lines(code: &str) -> Vec<String> {
+    code.split('\n').map(String::from).collect()
+}
+
+/// Replaces the patch's range with its text, and describes the change for tree-sitter. Fails
+/// if either end of the range is past the end of its line or of the document, falls inside a
+/// character, or if start comes after end.
+fn apply_patch(code: &str, patch: &Patch) -> Result<(String, InputEdit), String> {
+    let start = byte_offset(code, &patch.start)?;
+    let end = byte_offset(code, &patch.end)?;
+    if start > end {
+        return Err(format!(
+            "start {}:{} is after end {}:{}",
+            patch.start.line, patch.start.ch, patch.end.line, patch.end.ch
+        ));
+    }
+    let start_position = point_at(code, start, patch.start.line);
+    // Tree-sitter points count bytes, not UTF-16 units
+    let new_end_position = match patch.text.rfind('\n') {
+        Some(newline) => Point {
+            row: patch.start.line + patch.text.matches('\n').count(),
+            column: patch.text.len() - newline - 1,
+        },
+        None => Point {
+            row: patch.start.line,
+            column: start_position.column + patch.text.len(),
+        },
+    };
+    let edit = InputEdit {
+        start_byte: start,
+        old_end_byte: end,
+        new_end_byte: start + patch.text.len(),
+        start_position,
+        old_end_position: point_at(code, end, patch.end.line),
+        new_end_position,
+    };
+    Ok((
+        format!("{}{}{}", &code[..start], patch.text, &code[end..]),
+        edit,
+    ))
+}
+
+// Tree-sitter point of byte `offset`, which is on line `row`
+fn point_at(code: &str, offset: usize, row: usize) -> Point {
+    let line_start = code[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+    Point {
+        row,
+        column: offset - line_start,
+    }
+}
+
+// Byte index in `code` of a line / UTF-16 column position
+fn byte_offset(code: &str, position: &Position) -> Result<usize, String> {
+    let mut line_start = 0;
+    for line in 0..position.line {
+        match code[line_start..].find('\n') {
+            Some(newline) => line_start += newline + 1,
+            None => {
+                return Err(format!(
+                    "line {} is past the end of the document, which has {} lines",
+                    position.line,
+                    line + 1
+                ));
+            }
+        }
+    }
+    let line_end = code[line_start..]
+        .find('\n')
+        .map_or(code.len(), |newline| line_start + newline);
+    let mut units = 0;
+    for (index, c) in code[line_start..line_end].char_indices() {
+        if units == position.ch {
+            return Ok(line_start + index);
+        }
+        units += c.len_utf16();
+        if units > position.ch {
+            return Err(format!(
+                "ch {} on line {} falls inside a character",
+                position.ch, position.line
+            ));
+        }
+    }
+    if units == position.ch {
+        Ok(line_end)
+    } else {
+        Err(format!(
+            "ch {} is past the end of line {}, which is {} UTF-16 code units long",
+            position.ch, position.line, units
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::validation_services::language_validation::{
+        java_validator::JavaValidator, javascipt_validator::JavaScriptValidator,
+        python_validator::PythonValidator, validator::SyntaxValidator,
+    };
+    use proptest::prelude::*;
+
+    // Pieces random buffers are made of: ASCII, '\n' and "\r\n" line ends, BMP characters
+    // wider than a byte and astral ones that take a surrogate pair in UTF-16
+    const PIECES: &[&str] = &["a", "Z", " ", "\t", "\n", "\r\n", "é", "中", "😀", "𝄞"];
+
+    fn position(line: usize, ch: usize) -> Position {
+        Position { line, ch }
+    }
+
+    fn patch(start: Position, end: Position, text: &str) -> Patch {
+        Patch {
+            start,
+            end,
+            text: text.to_string(),
+        }
+    }
+
+    // Reference model: the buffer as UTF-16 code units, positions counted the way editors do
+
+    fn utf16(text: &str) -> Vec<u16> {
+        text.encode_utf16().collect()
+    }
+
+    fn model_lines(units: &[u16]) -> Vec<&[u16]> {
+        units.split(|unit| *unit == u16::from(b'\n')).collect()
+    }
+
+    fn model_offset(units: &[u16], position: &Position) -> Option<usize> {
+        let lines = model_lines(units);
+        let line = lines.get(position.line)?;
+        let splits_pair = position.ch > 0
+            && position.ch < line.len()
+            && (0xDC00..0xE000).contains(&line[position.ch]);
+        if position.ch > line.len() || splits_pair {
+            return None;
+        }
+        let line_start: usize = lines[..position.line]
+            .iter()
+            .map(|line| line.len() + 1)
+            .sum();
+        Some(line_start + position.ch)
+    }
+
+    fn model_apply(units: &[u16], patches: &[Patch]) -> Option<Vec<u16>> {
+        let mut units = units.to_vec();
+        for patch in patches {
+            let start = model_offset(&units, &patch.start)?;
+            let end = model_offset(&units, &patch.end)?;
+            if start > end {
+                return None;
+            }
+            units.splice(start..end, utf16(&patch.text));
+        }
+        Some(units)
+    }
+
+    fn text(max_pieces: usize) -> impl Strategy<Value = String> {
+        prop::collection::vec(prop::sample::select(PIECES), 0..max_pieces)
+            .prop_map(|pieces| pieces.concat())
+    }
+
+    // Buffers with positions mostly inside them, some a line or a few units past the end
+    fn buffer_and_positions() -> impl Strategy<Value = (String, Vec<Position>)> {
+        text(40).prop_flat_map(|code| {
+            let lines = code.split('\n').count();
+            let longest = code
+                .split('\n')
+                .map(|line| line.encode_utf16().count())
+                .max()
+                .unwrap_or(0);
+            let position =
+                (0..=lines, 0..=longest + 2).prop_map(|(line, ch)| Position { line, ch });
+            (Just(code), prop::collection::vec(position, 2..8))
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn patches_match_the_utf16_model(
+            (code, positions) in buffer_and_positions(),
+            texts in prop::collection::vec(text(4), 4),
+        ) {
+            let patches: Vec<Patch> = positions
+                .chunks_exact(2)
+                .zip(&texts)
+                .map(|(range, text)| patch(range[0], range[1], text))
+                .collect();
+            let mut editor = CodeEditor::new(&code);
+
+            let expected = model_apply(&utf16(&code), &patches);
+            match editor.apply_patches(0, &patches) {
+                Ok(_) => {
+                    let expected = expected.expect("the model rejects the patches");
+                    prop_assert_eq!(editor.to_string(), String::from_utf16(&expected).unwrap());
+                }
+                Err(PatchError::Invalid(_)) => {
+                    prop_assert!(expected.is_none(), "rejected patches the model applies");
+                    prop_assert_eq!(editor.to_string(), code);
+                    prop_assert_eq!(editor.revision, 0);
+                }
+                Err(e) => prop_assert!(false, "unexpected {:?}", e),
+            }
+        }
+
+        #[test]
+        fn offsets_match_the_utf16_model((code, positions) in buffer_and_positions()) {
+            let editor = CodeEditor::new(&code);
+            let units = utf16(&code);
+            for position in positions {
+                let expected = model_offset(&units, &position)
+                    .map(|offset| String::from_utf16(&units[..offset]).unwrap().len());
+                prop_assert_eq!(editor.offset_of(&position).ok(), expected);
+            }
+        }
+    }
+
+    fn assert_invalid(code: &str, patches: &[Patch], message: &str) {
+        let mut editor = CodeEditor::new(code);
+        match editor.apply_patches(0, patches) {
+            Err(PatchError::Invalid(error)) => {
+                assert!(error.contains(message), "{:?} lacks {:?}", error, message)
+            }
+            other => panic!("expected an invalid patch error, got {:?}", other),
+        }
+        assert_eq!(editor.to_string(), code);
+        assert_eq!(editor.revision, 0);
+    }
+
+    #[test]
+    fn a_ch_inside_a_surrogate_pair_is_rejected() {
+        // "😀" is units 1 and 2 of the line
+        let code = "a😀b";
+        let inside = position(0, 2);
+        assert_invalid(
+            code,
+            &[patch(inside, inside, "x")],
+            "falls inside a character",
+        );
+        assert_invalid(
+            code,
+            &[patch(position(0, 0), inside, "")],
+            "falls inside a character",
+        );
+    }
+
+    #[test]
+    fn a_ch_past_the_end_of_the_line_is_rejected() {
+        let code = "ab\r\nc";
+        // The '\r' is part of the first line
+        let end = position(0, 3);
+        let mut editor = CodeEditor::new(code);
+        editor.apply_patches(0, &[patch(end, end, "!")]).unwrap();
+        assert_eq!(editor.to_string(), "ab\r!\nc");
+        assert_invalid(
+            code,
+            &[patch(position(0, 4), position(0, 4), "x")],
+            "past the end of line 0",
+        );
+    }
+
+    #[test]
+    fn a_line_past_the_end_of_the_document_is_rejected() {
+        let code = "first\nsecond\n";
+        // The trailing newline starts an empty third line
+        let mut editor = CodeEditor::new(code);
+        let last = position(2, 0);
+        editor
+            .apply_patches(0, &[patch(last, last, "third")])
+            .unwrap();
+        assert_eq!(editor.to_string(), "first\nsecond\nthird");
+        assert_invalid(
+            code,
+            &[patch(position(3, 0), position(3, 0), "x")],
+            "line 3 is past the end of the document",
+        );
+    }
+
+    #[test]
+    fn a_start_after_the_end_is_rejected() {
+        assert_invalid(
+            "one\ntwo",
+            &[patch(position(1, 1), position(0, 2), "x")],
+            "start 1:1 is after end 0:2",
+        );
+    }
+
+    #[test]
+    fn a_failing_patch_leaves_the_whole_message_unapplied() {
+        let start = position(0, 0);
+        assert_invalid(
+            "abc",
+            &[
+                patch(start, start, "ok"),
+                patch(position(5, 0), position(5, 0), "x"),
+            ],
+            "(patch 1)",
+        );
+    }
+
+    // `message` as it applies after `other`, each of its patches moved past `other`'s and
+    // those past it, as `apply_patches` does; `None` if they overlap
+    fn transformed_message(message: &[Patch], other: &[Patch], after: bool) -> Option<Vec<Patch>> {
+        let mut other = other.to_vec();
+        let mut transformed = Vec::new();
+        for patch in message {
+            let mut patch = patch.clone();
+            for change in other.iter_mut() {
+                if patch.overlaps(change) {
+                    return None;
+                }
+                let moved = patch.transformed(change, after);
+                *change = change.transformed(&patch, !after);
+                patch = moved;
+            }
+            transformed.push(patch);
+        }
+        Some(transformed)
+    }
+
+    // Applies `a` then `b`, both made against `code`, the way the server does (`b` is moved
+    // past `a`), and checks a client that applied `b` first and then `a` moved past it ends up
+    // with the same buffer
+    fn assert_converges(code: &str, a: &[Patch], b: &[Patch]) -> String {
+        let mut server = CodeEditor::new(code);
+        server.apply_patches(0, a).unwrap();
+        server.apply_patches(0, b).unwrap();
+
+        let mut client = CodeEditor::new(code);
+        client.apply_patches(0, b).unwrap();
+        let a_after_b = transformed_message(a, b, false).unwrap();
+        client.apply_patches(client.revision, &a_after_b).unwrap();
+
+        assert_eq!(server.to_string(), client.to_string());
+        server.to_string()
+    }
+
+    #[test]
+    fn inserts_at_the_same_position_converge_in_server_order() {
+        let at = position(0, 3);
+        let merged = assert_converges("abcdef", &[patch(at, at, "X")], &[patch(at, at, "Y")]);
+        assert_eq!(merged, "abcXYdef");
+        // At the start and the end of a line, and with line breaks in the inserted text
+        for at in [position(1, 0), position(1, 5)] {
+            assert_converges(
+                "one\ntwo😀\n",
+                &[patch(at, at, "1\n2")],
+                &[patch(at, at, "é")],
+            );
+        }
+    }
+
+    #[test]
+    fn touching_replaces_converge() {
+        let code = "0123456789";
+        // Each side's replaced range ends where the other's starts
+        let merged = assert_converges(
+            code,
+            &[patch(position(0, 2), position(0, 5), "ab")],
+            &[patch(position(0, 5), position(0, 8), "XYZW")],
+        );
+        assert_eq!(merged, "01abXYZW89");
+        let merged = assert_converges(
+            code,
+            &[patch(position(0, 5), position(0, 8), "XYZW")],
+            &[patch(position(0, 2), position(0, 5), "ab")],
+        );
+        assert_eq!(merged, "01abXYZW89");
+        // An insertion at either end of a replaced range stays outside it
+        for at in [position(0, 2), position(0, 5)] {
+            assert_converges(
+                code,
+                &[patch(position(0, 2), position(0, 5), "r")],
+                &[patch(at, at, "i")],
+            );
+            assert_converges(
+                code,
+                &[patch(at, at, "i")],
+                &[patch(position(0, 2), position(0, 5), "r")],
+            );
+        }
+    }
+
+    #[test]
+    fn replaces_across_lines_converge() {
+        let merged = assert_converges(
+            "def f():\n    return 1\n\nprint(f())",
+            &[patch(position(0, 4), position(1, 4), "g():\n  ")],
+            &[patch(position(3, 6), position(3, 9), "g()")],
+        );
+        assert_eq!(merged, "def g():\n  return 1\n\nprint(g())");
+    }
+
+    #[test]
+    fn multi_patch_messages_converge() {
+        let code = "alpha\nbeta\ngamma";
+        // Each message's later patches apply to the buffer its earlier ones left
+        let a = [
+            patch(position(0, 0), position(0, 0), "# 😀\n"),
+            patch(position(2, 0), position(2, 4), "BETA"),
+            patch(position(3, 5), position(3, 5), "!"),
+        ];
+        let b = [
+            patch(position(0, 5), position(0, 5), " one"),
+            patch(position(1, 0), position(1, 0), "x"),
+            patch(position(2, 0), position(2, 0), "\n"),
+        ];
+        let merged = assert_converges(code, &a, &b);
+        assert_eq!(merged, "# 😀\nalpha one\nxBETA\n\ngamma!");
+        assert_converges(code, &b, &a);
+    }
+
+    fn single_patch(code: &str) -> impl Strategy<Value = Patch> + use<> {
+        let lines: Vec<Vec<usize>> = code
+            .split('\n')
+            .map(|line| {
+                // Every UTF-16 column between characters
+                let mut columns = vec![0];
+                for c in line.chars() {
+                    columns.push(columns.last().unwrap() + c.len_utf16());
+                }
+                columns
+            })
+            .collect();
+        let positions: Vec<Position> = lines
+            .iter()
+            .enumerate()
+            .flat_map(|(line, columns)| columns.iter().map(move |&ch| position(line, ch)))
+            .collect();
+        (
+            prop::sample::select(positions.clone()),
+            prop::sample::select(positions),
+            text(3),
+        )
+            .prop_map(|(start, end, text)| patch(start.min(end), start.max(end), &text))
+    }
+
+    proptest! {
+        #[test]
+        fn concurrent_patches_converge(
+            (code, a, b) in text(30).prop_flat_map(|code| {
+                let a = single_patch(&code);
+                let b = single_patch(&code);
+                (Just(code), a, b)
+            }),
+        ) {
+            prop_assume!(!a.overlaps(&b));
+            assert_converges(&code, &[a], &[b]);
+        }
+    }
+
+    fn assert_stale(result: Result<Vec<Patch>, PatchError>, expected_base: u64) {
+        match result {
+            Err(PatchError::Stale { base, .. }) => assert_eq!(base, expected_base),
+            other => panic!("expected a stale patch error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn overlapping_concurrent_patches_are_stale() {
+        let mut editor = CodeEditor::new("hello world");
+        editor
+            .apply_patches(0, &[patch(position(0, 0), position(0, 5), "bye")])
+            .unwrap();
+        // Replaces part of the text the first patch replaced
+        assert_stale(
+            editor.apply_patches(0, &[patch(position(0, 3), position(0, 8), "p")]),
+            0,
+        );
+        // Inserts inside it
+        let inside = position(0, 2);
+        assert_stale(editor.apply_patches(0, &[patch(inside, inside, "p")]), 0);
+        assert_eq!(editor.to_string(), "bye world");
+    }
+
+    #[test]
+    fn a_base_older_than_the_history_is_stale() {
+        let mut editor = CodeEditor::new("");
+        let end = |editor: &CodeEditor| position(0, editor.lines[0].len());
+        for _ in 0..=HISTORY_LIMIT {
+            let at = end(&editor);
+            editor
+                .apply_patches(editor.revision, &[patch(at, at, "x")])
+                .unwrap();
+        }
+        let start = position(0, 0);
+        // Revision 1's change was dropped from the history, revision 2's is the oldest kept
+        assert_stale(editor.apply_patches(0, &[patch(start, start, "y")]), 0);
+        editor
+            .apply_patches(1, &[patch(start, start, "y")])
+            .unwrap();
+        assert!(editor.to_string().starts_with('y'));
+    }
+
+    #[test]
+    fn a_base_before_a_full_replacement_is_stale() {
+        let mut editor = CodeEditor::new("print(1)");
+        let at = position(0, 8);
+        editor.apply_patches(0, &[patch(at, at, "\n")]).unwrap();
+        editor.update_from_string("print(2)\n");
+        let start = position(0, 0);
+        for base in [0, 1] {
+            assert_stale(
+                editor.apply_patches(base, &[patch(start, start, "#")]),
+                base,
+            );
+        }
+        editor
+            .apply_patches(editor.revision, &[patch(start, start, "#")])
+            .unwrap();
+        assert_eq!(editor.to_string(), "#print(2)\n");
+    }
+
+    // Every node with its byte range and points, so a tree edited with wrong offsets or
+    // points doesn't compare equal just because its shape is right
+    fn nodes(tree: &tree_sitter::Tree) -> Vec<String> {
+        let mut nodes = Vec::new();
+        let mut cursor = tree.walk();
+        'walk: loop {
+            let node = cursor.node();
+            nodes.push(format!(
+                "{} {:?} {:?}-{:?}",
+                node.kind(),
+                node.byte_range(),
+                node.start_position(),
+                node.end_position()
+            ));
+            if cursor.goto_first_child() || cursor.goto_next_sibling() {
+                continue;
+            }
+            while cursor.goto_parent() {
+                if cursor.goto_next_sibling() {
+                    continue 'walk;
+                }
+            }
+            break;
+        }
+        nodes
+    }
+
+    // Applies each message in turn, editing the carried syntax tree and reparsing from it, and
+    // checks the result against a fresh parse of the buffer every time
+    fn assert_reparses_like_fresh(validator: &dyn SyntaxValidator, code: &str, edits: &[&[Patch]]) {
+        let mut editor = CodeEditor::new(code);
+        editor.syntax_tree = Some(validator.parse(code).unwrap());
+        for (step, patches) in edits.iter().enumerate() {
+            editor.apply_patches(editor.revision, patches).unwrap();
+            let code = editor.to_string();
+            let reparsed = validator
+                .reparse(&code, editor.syntax_tree.as_ref())
+                .unwrap();
+            let fresh = validator.parse(&code).unwrap();
+            assert_eq!(
+                reparsed.root_node().to_sexp(),
+                fresh.root_node().to_sexp(),
+                "step {} of {:?}",
+                step,
+                code
+            );
+            assert_eq!(
+                nodes(&reparsed),
+                nodes(&fresh),
+                "step {} of {:?}",
+                step,
+                code
+            );
+            editor.syntax_tree = Some(reparsed);
+        }
+    }
+
+    #[test]
+    fn incremental_reparses_match_fresh_parses() {
+        let at = |line, ch| position(line, ch);
+        let insert = |line, ch, text| patch(at(line, ch), at(line, ch), text);
+        assert_reparses_like_fresh(
+            &PythonValidator,
+            "def f(x):\n    return x\n\nprint(f(1))\n",
+            &[
+                &[insert(1, 12, " + 1")],
+                // Multi-line insert in the middle of a line
+                &[insert(0, 9, "\n    \"\"\"Doc é😀\"\"\"")],
+                &[insert(4, 0, "class Ü😀:\n    pass\n\n")],
+                // Replace across lines, then break and fix the syntax
+                &[patch(at(1, 4), at(2, 12), "return '日本'")],
+                &[insert(1, 16, "(")],
+                &[patch(at(1, 16), at(1, 17), "")],
+                // Several patches in one message, the second on the buffer the first left
+                &[insert(0, 0, "# 😀\n"), insert(1, 6, "y, ")],
+                // Multi-line inserts in the middle of a line move what follows to another row
+                &[insert(1, 6, "\n    zé,\n    ")],
+            ],
+        );
+        assert_reparses_like_fresh(
+            &JavaScriptValidator,
+            "function f(a) {\n  return a;\n}\n",
+            &[
+                &[insert(1, 10, " * 2")],
+                &[insert(0, 0, "const s = '😀é';\n")],
+                &[insert(2, 14, "\n  const t = `${s} ü`;")],
+                &[patch(at(0, 11), at(0, 13), "日本")],
+                &[insert(4, 1, "\nf(1")],
+                &[insert(5, 3, ");")],
+                &[insert(5, 2, "\n  '😀',\n  ")],
+            ],
+        );
+        assert_reparses_like_fresh(
+            &JavaValidator,
+            "class Main {\n    public static void main(String[] args) {\n    }\n}\n",
+            &[
+                &[insert(1, 44, "\n        String s = \"é😀\";")],
+                &[insert(2, 25, "\n        System.out.println(s)")],
+                &[insert(3, 29, ";")],
+                &[patch(at(2, 20), at(2, 23), "日本")],
+                &[insert(0, 0, "// ü\n")],
+                &[insert(2, 28, "\n            final ")],
+            ],
+        );
+    }
+
+    #[test]
+    fn input_edits_point_at_the_edited_bytes() {
+        let code = "ab😀c\néd\n";
+        for patch in [
+            patch(position(0, 1), position(0, 1), "é"),
+            patch(position(0, 4), position(1, 1), "x\n日本"),
+            patch(position(0, 4), position(0, 5), "\n\n"),
+            patch(position(1, 2), position(2, 0), ""),
+        ] {
+            let (patched, edit) = apply_patch(code, &patch).unwrap();
+            // Tree-sitter points: rows and byte columns
+            let point = |code: &str, byte: usize| {
+                let before = &code[..byte];
+                Point {
+                    row: before.matches('\n').count(),
+                    column: byte - before.rfind('\n').map_or(0, |newline| newline + 1),
+                }
+            };
+            assert_eq!(edit.start_position, point(code, edit.start_byte));
+            assert_eq!(edit.old_end_position, point(code, edit.old_end_byte));
+            assert_eq!(edit.new_end_position, point(&patched, edit.new_end_byte));
+        }
+    }
 }
@@ -1,20 +1,33 @@
-use std::str::FromStr;
 use tonic::{Request, Response, Status};
 
 use crate::{
     docker::docker_manager,
     models::{
-        docker_models::DockerSupportedLanguage,
-        executor_models::ExecutorService,
+        cleanup_models::CleanupService,
+        executor_models::{ExecutionResult, ExecutorService, RunOptions},
+        session_management_models::{SessionError, SessionKey},
         validation_models::{ValidRequest, ValidationError, ValidationService},
     },
-    proto::executor::{ExecuteRequest, ExecuteResponse, code_executor_server::CodeExecutor},
+    proto::executor::{
+        CloseSessionResponse, CreateSessionRequest, ExecuteRequest, ExecuteResponse,
+        ListSessionsRequest, ListSessionsResponse, OutlineRequest, OutlineResponse, SessionInfo,
+        SessionRef, ValidateRequest, ValidateResponse, code_executor_server::CodeExecutor,
+    },
     services::{
-        all_session_services::session_management_service::SessionManagement,
-        helper_services::config_service::get_global_config,
+        all_session_services::{
+            session_lifecycle_service::{self, capped_limits},
+            session_management_service::{SessionManagement, SessionManagementService},
+            session_service,
+        },
+        helper_services::{
+            config_service::get_global_config, container_events_service,
+            language_registry_service::get_language_registry, metrics_service::get_metrics,
+        },
     },
 };
 
+pub const RETRY_AFTER: &str = "retry-after";
+
 #[tonic::async_trait]
 impl CodeExecutor for ExecutorService {
     async fn execute(
@@ -31,49 +44,241 @@ impl CodeExecutor for ExecutorService {
                 )));
             }
         };
-        match session_handler(valid_data).await {
-            Ok(output) => {
-                println!("Execution Result: {}", output);
-                Ok(Response::new(ExecuteResponse { message: output }))
+        let options = RunOptions {
+            stdin: Some(request.get_ref().stdin.clone()).filter(|stdin| !stdin.is_empty()),
+            ..Default::default()
+        };
+        match session_handler(valid_data, options).await {
+            Ok(result) => {
+                println!(
+                    "Execution Result ({}): {}",
+                    result.runtime_version, result.output
+                );
+                Ok(Response::new(ExecuteResponse {
+                    message: result.output,
+                    runtime_version: result.runtime_version,
+                    session_ttl_seconds: result.session_ttl_secs as i64,
+                    concurrency: result.disposition.as_str().to_string(),
+                    environment_lost: result.environment_lost.unwrap_or_default(),
+                }))
             }
             Err(e) => {
                 eprintln!("Error: {}", e);
-                Err(Status::internal(format!("Execution error: {}", e)))
+                Err(execution_error_status(e.as_ref()))
+            }
+        }
+    }
+
+    async fn create_session(
+        &self,
+        request: Request<CreateSessionRequest>,
+    ) -> Result<Response<SessionInfo>, Status> {
+        session_lifecycle_service::create_session(request.into_inner())
+            .await
+            .map(Response::new)
+    }
+
+    async fn get_session(
+        &self,
+        request: Request<SessionRef>,
+    ) -> Result<Response<SessionInfo>, Status> {
+        session_lifecycle_service::get_session(request.into_inner())
+            .await
+            .map(Response::new)
+    }
+
+    async fn list_sessions(
+        &self,
+        request: Request<ListSessionsRequest>,
+    ) -> Result<Response<ListSessionsResponse>, Status> {
+        session_lifecycle_service::list_sessions(request.into_inner())
+            .await
+            .map(Response::new)
+    }
+
+    async fn keep_alive(
+        &self,
+        request: Request<SessionRef>,
+    ) -> Result<Response<SessionInfo>, Status> {
+        session_lifecycle_service::keep_alive(request.into_inner())
+            .await
+            .map(Response::new)
+    }
+
+    async fn close_session(
+        &self,
+        request: Request<SessionRef>,
+    ) -> Result<Response<CloseSessionResponse>, Status> {
+        session_lifecycle_service::close_session(request.into_inner())
+            .await
+            .map(Response::new)
+    }
+
+    async fn validate(
+        &self,
+        request: Request<ValidateRequest>,
+    ) -> Result<Response<ValidateResponse>, Status> {
+        ValidationService::validate(request.into_inner())
+            .map(Response::new)
+            .map_err(|e| Status::invalid_argument(e.to_string()))
+    }
+
+    async fn get_outline(
+        &self,
+        request: Request<OutlineRequest>,
+    ) -> Result<Response<OutlineResponse>, Status> {
+        ValidationService::get_outline(request.into_inner())
+            .map(Response::new)
+            .map_err(|e| Status::invalid_argument(e.to_string()))
+    }
+}
+
+/// Maps an execution failure to a gRPC status; a full session pool becomes
+/// `RESOURCE_EXHAUSTED` with a `retry-after` (seconds) metadata entry, a busy session under
+/// the `reject` policy `ABORTED`, a cancelled run `CANCELLED`, and a session
+/// whose container died `FAILED_PRECONDITION`.
+pub fn execution_error_status(error: &(dyn std::error::Error + 'static)) -> Status {
+    match error.downcast_ref::<SessionError>() {
+        Some(SessionError::ResourceExhausted(retry_after)) => {
+            let mut status = Status::resource_exhausted(error.to_string());
+            if let Ok(value) = retry_after.to_string().parse() {
+                status.metadata_mut().insert(RETRY_AFTER, value);
             }
+            status
         }
+        Some(SessionError::Busy(_)) => Status::aborted(error.to_string()),
+        Some(SessionError::Cancelled(_)) => Status::cancelled(error.to_string()),
+        Some(SessionError::EnvironmentLost(_)) => Status::failed_precondition(error.to_string()),
+        _ => Status::internal(format!("Execution error: {}", error)),
     }
 }
 
-pub async fn session_handler(data: ValidRequest) -> Result<String, Box<dyn std::error::Error>> {
+/// Runs a request in its session. A request without code runs the session's current buffer
+/// (e.g. what was edited over the WebSocket); otherwise the request's code becomes the buffer.
+/// The session's container is started on its first run. Runs of one session never overlap,
+/// see `SessionManagement::acquire_run`. `options` carries the run's stdin, limits below the
+/// session's, and where to stream output (the WebSocket `run` message).
+pub async fn session_handler(
+    data: ValidRequest,
+    options: RunOptions,
+) -> Result<ExecutionResult, Box<dyn std::error::Error>> {
     let session_id = data.get_session_id();
-    let language = data.get_language();
-    let language_str = language.to_string();
-    let code = data.get_code();
-    println!("Handling request for language: {}", language);
+    let language_str = data.get_language().to_string();
+    println!("Handling request for language: {}", language_str);
+    let mut language = match get_language_registry().resolve(&language_str) {
+        Some(definition) => definition,
+        None => {
+            eprintln!("Unsupported language: {}", language_str);
+            return Err(Box::new(ValidationError::InvalidLanguage(language_str)));
+        }
+    };
 
     let global_config_instance = get_global_config(|config| config.clone())
         .await
         .session_management_service
         .unwrap();
-    match global_config_instance
-        .get_session_image(session_id, &language_str)
-        .await
-    {
-        Ok(image) => {
-            println!("Session image for {}: {}", session_id, image);
-            let language = match DockerSupportedLanguage::from_str(language) {
-                Ok(lang) => lang,
-                Err(_) => {
-                    eprintln!("Unsupported language: {}", language);
-                    return Err(Box::new(ValidationError::InvalidLanguage(
-                        language.to_string(),
-                    )));
-                }
-            };
-            match docker_manager::execute_code_in_existing_container(&image, language, code).await {
-                Ok(result) => {
-                    println!("Execution Result: {}", result);
-                    Ok(result)
+    let key = SessionKey::new(session_id.to_string(), language_str.clone());
+    let buffer = if data.get_code().is_empty() {
+        let buffer = global_config_instance
+            .get_session(session_id, &language_str)
+            .await
+            .map(|session| session.editor.to_string())
+            .unwrap_or_default();
+        if buffer.trim().is_empty() {
+            return Err(Box::new(ValidationError::EmptyCode()));
+        }
+        ValidationService::validate_code(&language, &buffer)?;
+        Some(buffer)
+    } else {
+        // Creates the session if needed, making room under max_sessions before any container
+        // is built
+        session_service::open_session(&key).await?;
+        None
+    };
+    let permit = global_config_instance
+        .acquire_run(session_id, &language_str)
+        .await?;
+    // Only a run that got its turn replaces the buffer; a rejected one leaves it as it was
+    let code = match buffer {
+        Some(buffer) => buffer,
+        None => {
+            session_service::replace_code(&key, data.get_code()).await?;
+            data.get_code().to_string()
+        }
+    };
+    // The run we waited for may have started the container
+    let mut session = global_config_instance
+        .get_session(session_id, &language_str)
+        .await?;
+    // The container died since the last run, see `container_events_service`
+    let environment_lost = session.environment_lost.clone();
+    if let Some(reason) = &environment_lost {
+        let recreate = get_global_config(|config| config.clone())
+            .await
+            .session_configs
+            .recreate_lost_containers;
+        if !recreate {
+            return Err(Box::new(SessionError::EnvironmentLost(reason.clone())));
+        }
+        // Removed before the new container is started under the same name
+        if let Some(lost) = global_config_instance
+            .detach_container(session_id, &language_str)
+            .await?
+        {
+            CleanupService::remove_session_resources(&lost.name, lost.session_image.as_deref())
+                .await;
+        }
+        println!(
+            "Recreating the container of session {} ({})",
+            session_id, reason
+        );
+        get_metrics().increment("session_environment_recreated");
+        session.container = None;
+        session.paused = false;
+    }
+    // Idle containers are paused, see `SessionManagement::pause_idle_sessions`
+    if session.paused {
+        global_config_instance
+            .resume_session(session_id, &language_str)
+            .await?;
+    }
+    // Limits given to CreateSession apply to the container and every run
+    if let Some(limits) = session.limits.clone() {
+        language.config.limits = limits;
+    }
+    if let Some(limits) = &options.limits {
+        language.config.limits = capped_limits(limits, &language.config.limits);
+    }
+
+    let result = match session.container.clone() {
+        Some(container) => {
+            println!("Session container for {}: {}", session_id, container.name);
+            global_config_instance
+                .begin_execution(session_id, &language_str)
+                .await;
+            let execution = docker_manager::execute_code_in_existing_container(
+                &container.name,
+                &language,
+                &code,
+                &container.image_digest,
+                &permit.token,
+                &options,
+            )
+            .await
+            .map_err(|e| e.to_string()); // Box<dyn Error> isn't Send, don't hold it across .await
+            global_config_instance
+                .end_execution(session_id, &language_str)
+                .await;
+            match execution {
+                Ok(output) => {
+                    println!("Execution Result: {}", output);
+                    Ok(ExecutionResult {
+                        output,
+                        runtime_version: container.runtime_version,
+                        session_ttl_secs: session.remaining_ttl().as_secs(),
+                        disposition: permit.disposition,
+                        environment_lost: None,
+                    })
                 }
                 Err(e) => {
                     eprintln!("Error executing code in container: {:?}", e);
@@ -81,10 +286,85 @@ pub async fn session_handler(data: ValidRequest) -> Result<String, Box<dyn std::
                 }
             }
         }
-        Err(e) => {
-            eprintln!("image not found {:?}, creating new image", e);
-            let result = docker_manager::handle_request(session_id, language, code).await?;
-            Ok(result)
+        None => {
+            println!("No container for session {}, starting one", session_id);
+            docker_manager::handle_request(session_id, &language, &code, &permit.token, &options)
+                .await
+                .map(|result| ExecutionResult {
+                    disposition: permit.disposition,
+                    environment_lost,
+                    ..result
+                })
+                .map_err(|e| e.to_string())
         }
+    };
+    if permit.token.is_cancelled() {
+        return Err(Box::new(SessionError::Cancelled(format!(
+            "{}:{}",
+            session_id, language_str
+        ))));
+    }
+    // A run that failed because the container died reports why instead of the Docker error
+    if result.is_err()
+        && let Some(reason) =
+            lost_environment(global_config_instance, session_id, &language_str).await
+    {
+        return Err(Box::new(SessionError::EnvironmentLost(reason)));
+    }
+    Ok(result?)
+}
+
+// Why the session's container is gone, or `None` if it still runs. The container is inspected
+// when the events stream hasn't reported its death yet.
+async fn lost_environment(
+    sessions: &SessionManagementService,
+    session_id: &str,
+    language: &str,
+) -> Option<String> {
+    let (_, session) = sessions.find_session(session_id, language).await.ok()?;
+    if session.environment_lost.is_some() {
+        return session.environment_lost;
+    }
+    let container = session.container?;
+    let reason = container_events_service::container_exit_reason(&container.id).await?;
+    sessions.mark_environment_lost(&container.id, &reason).await;
+    Some(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::helper_services::config_service::init_test_sessions;
+
+    #[tokio::test]
+    async fn a_rejected_run_leaves_the_buffer_unchanged() {
+        let sessions = init_test_sessions().await;
+        sessions
+            .update_code("rejected-run", "python", "print(1)")
+            .await
+            .unwrap();
+        let running = sessions
+            .acquire_run("rejected-run", "python")
+            .await
+            .unwrap();
+
+        let request = ValidRequest::new(
+            "rejected-run".to_string(),
+            "print(2)".to_string(),
+            "python".to_string(),
+        );
+        let error = session_handler(request, RunOptions::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<SessionError>(),
+            Some(SessionError::Busy(_))
+        ));
+        let session = sessions
+            .get_session("rejected-run", "python")
+            .await
+            .unwrap();
+        assert_eq!(session.editor.to_string(), "print(1)");
+        drop(running);
     }
 }
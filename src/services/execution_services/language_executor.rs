@@ -1,22 +1,53 @@
 use std::error::Error;
 
-use crate::models::docker_models::DockerSupportedLanguage;
+use crate::{
+    models::language_registry_models::LanguageDefinition,
+    services::helper_services::config_service::get_global_config, utils::hash_utils::stable_hash,
+};
 
-pub fn generate_shell_command(
-    language: DockerSupportedLanguage,
+/// Builds the `sh -c` command that writes, compiles (if needed) and runs `code`.
+///
+/// Compiled languages build inside `<build_cache_path>/<hash>`, keyed by the source, the
+/// compile command and the image digest, so re-running the same code in a session only
+/// executes the run step.
+pub async fn generate_shell_command(
+    language: &LanguageDefinition,
     code: &str,
+    image_digest: &str,
 ) -> Result<String, Box<dyn Error>> {
-    match language {
-        DockerSupportedLanguage::Python => {
-            Ok(format!("echo '{}' > script.py && python script.py", code))
-        }
-        DockerSupportedLanguage::JavaScript => {
-            Ok(format!("echo '{}' > script.js && node script.js", code))
+    if language.config.run_command.trim().is_empty() {
+        return Err(format!("No run command configured for language: {}", language.name).into());
+    }
+    let entry_point = language.entry_point(code)?;
+    let entry_point = entry_point.as_ref();
+    let write_source = format!(
+        "echo '{}' > {}",
+        code,
+        language.source_file_name(entry_point)
+    );
+    match language.compile_command(entry_point) {
+        Some(compile) => {
+            let build_cache_path = get_global_config(|config| config.clone())
+                .await
+                .paths
+                .build_cache_path;
+            let build_dir = format!(
+                "{}/{}",
+                build_cache_path.trim_end_matches('/'),
+                stable_hash(&[code, &compile, image_digest])
+            );
+            Ok(format!(
+                "mkdir -p {dir} && cd {dir} && if [ ! -f .compiled ]; then {} && {} && touch .compiled; fi && {}",
+                write_source,
+                compile,
+                language.run_command(entry_point),
+                dir = build_dir
+            ))
         }
-        DockerSupportedLanguage::Java => Ok(format!(
-            "echo '{}' > Main.java && javac Main.java && java Main",
-            code
+        None => Ok(format!(
+            "{} && {}",
+            write_source,
+            language.run_command(entry_point)
         )),
-        // _ => Err(format!("Unsupported language: {:?}",DockerSupportedLanguage::to_string(&language)).into()),
     }
 }
@@ -1,12 +1,20 @@
-use bollard::Docker;
+use bollard::container::ListContainersOptions;
 use bollard::container::RemoveContainerOptions;
+use bollard::errors::Error as BollardError;
+use bollard::image::{ListImagesOptions, RemoveImageOptions};
+use std::collections::HashSet;
 use std::fs;
 use std::path::Path;
 use std::process::Command;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use crate::{
-    models::cleanup_models::{ActivityType, CleanupService},
-    services::helper_services::config_service::get_global_config,
+    models::{
+        cleanup_models::{ActivityType, CleanupService},
+        session_management_models::SessionManagementService,
+    },
+    services::helper_services::{config_service::get_global_config, metrics_service::get_metrics},
+    utils::docker_utils::get_docker_instance,
 };
 
 pub const CLEANUP_ACTIVITY_CONTAINER: &str = "container";
@@ -46,9 +54,228 @@ impl CleanupService {
         Ok(())
     }
 
+    /// Force-removes a session's container and, if it had one, its per-session image.
+    /// Each removal is retried `session_configs.teardown_retries` times with exponential
+    /// backoff; outcomes are logged and counted in metrics.
+    pub async fn remove_session_resources(container: &str, session_image: Option<&str>) {
+        let started = Instant::now();
+        let docker = match get_docker_instance() {
+            Ok(docker) => docker,
+            Err(e) => {
+                eprintln!("Cannot tear down session container '{}': {}", container, e);
+                get_metrics().increment("session_container_removal_failed");
+                return;
+            }
+        };
+        let retries = get_global_config(|config| config.clone())
+            .await
+            .session_configs
+            .teardown_retries
+            .max(1);
+
+        let removed = Self::with_retries(retries, "container", container, || {
+            docker.remove_container(
+                container,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+        })
+        .await;
+        get_metrics().increment(if removed {
+            "session_container_removed"
+        } else {
+            "session_container_removal_failed"
+        });
+
+        if let Some(image) = session_image {
+            let removed = Self::with_retries(retries, "image", image, || async {
+                docker
+                    .remove_image(
+                        image,
+                        Some(RemoveImageOptions {
+                            force: true,
+                            ..Default::default()
+                        }),
+                        None,
+                    )
+                    .await
+                    .map(|_| ())
+            })
+            .await;
+            get_metrics().increment(if removed {
+                "session_image_removed"
+            } else {
+                "session_image_removal_failed"
+            });
+        }
+        get_metrics().observe("session_teardown", started.elapsed());
+    }
+
+    async fn with_retries<F, Fut>(retries: u32, kind: &str, name: &str, mut remove: F) -> bool
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<(), BollardError>>,
+    {
+        for attempt in 1..=retries {
+            match remove().await {
+                Ok(()) => {
+                    println!("Removed session {} '{}'", kind, name);
+                    return true;
+                }
+                // Already gone, e.g. removed manually or by a previous attempt
+                Err(BollardError::DockerResponseServerError {
+                    status_code: 404, ..
+                }) => {
+                    println!("Session {} '{}' was already removed", kind, name);
+                    return true;
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Attempt {}/{} to remove session {} '{}' failed: {}",
+                        attempt, retries, kind, name, e
+                    );
+                    if attempt < retries {
+                        tokio::time::sleep(Duration::from_secs(1 << (attempt - 1))).await;
+                    }
+                }
+            }
+        }
+        eprintln!("Giving up on removing session {} '{}'", kind, name);
+        false
+    }
+
+    /// Finds containers and images labelled by any Rexec instance (this one or one that
+    /// crashed). Those owned by a live session are adopted; the rest are removed once they
+    /// are older than `session_configs.orphan_grace_period`, which leaves containers that are
+    /// still being set up alone.
+    pub async fn reconcile_orphans(sessions: &SessionManagementService) {
+        let started = Instant::now();
+        if let Err(e) = Self::reconcile(sessions).await {
+            eprintln!("Reconciliation failed: {}", e);
+            get_metrics().increment("reconcile_failed");
+        }
+        get_metrics().observe("reconcile", started.elapsed());
+    }
+
+    async fn reconcile(
+        sessions: &SessionManagementService,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let docker = get_docker_instance()?;
+        let config = get_global_config(|config| config.clone()).await;
+        let created_by_tag = config.constants.docker_created_by_label.clone();
+        // Every instance labels with "<service_name> <pod id>", so match on the prefix
+        let service_name = config.constants.service_name.clone();
+        let created_before = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() as i64)
+            .unwrap_or(0)
+            - config.session_configs.orphan_grace_period as i64;
+        let (owned_containers, owned_images) = sessions.session_resources().await;
+
+        let containers = docker
+            .list_containers(Some(ListContainersOptions::<String> {
+                all: true,
+                filters: [("label".to_string(), vec![created_by_tag.clone()])]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            }))
+            .await?;
+        // Images still used by an adopted container are kept as well
+        let mut images_in_use = HashSet::new();
+        for container in containers {
+            let from_rexec = container
+                .labels
+                .as_ref()
+                .and_then(|labels| labels.get(&created_by_tag))
+                .is_some_and(|label| label.starts_with(&service_name));
+            if !from_rexec {
+                continue;
+            }
+            let Some(id) = container.id.clone() else {
+                continue;
+            };
+            let names: Vec<String> = container
+                .names
+                .unwrap_or_default()
+                .iter()
+                .map(|name| name.trim_start_matches('/').to_string())
+                .collect();
+            if names.iter().any(|name| owned_containers.contains(name)) {
+                get_metrics().increment("reconcile_container_adopted");
+                images_in_use.extend(container.image_id);
+                continue;
+            }
+            if container.created.unwrap_or(0) > created_before {
+                images_in_use.extend(container.image_id);
+                continue;
+            }
+            println!("Removing orphaned container {:?} ({})", names, id);
+            match docker
+                .remove_container(
+                    &id,
+                    Some(RemoveContainerOptions {
+                        force: true,
+                        ..Default::default()
+                    }),
+                )
+                .await
+            {
+                Ok(()) => get_metrics().increment("reconcile_container_removed"),
+                Err(e) => eprintln!("Failed to remove orphaned container {}: {}", id, e),
+            }
+        }
+
+        let images = docker
+            .list_images(Some(ListImagesOptions::<String> {
+                filters: [("label".to_string(), vec![created_by_tag.clone()])]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            }))
+            .await?;
+        for image in images {
+            let from_rexec = image
+                .labels
+                .get(&created_by_tag)
+                .is_some_and(|label| label.starts_with(&service_name));
+            if !from_rexec {
+                continue;
+            }
+            let owned = images_in_use.contains(&image.id)
+                || image.repo_tags.iter().any(|tag| {
+                    owned_images.contains(tag)
+                        || owned_images.contains(tag.trim_end_matches(":latest"))
+                });
+            if owned || image.created > created_before {
+                continue;
+            }
+            println!(
+                "Removing orphaned image {:?} ({})",
+                image.repo_tags, image.id
+            );
+            match docker
+                .remove_image(
+                    &image.id,
+                    Some(RemoveImageOptions {
+                        force: true,
+                        ..Default::default()
+                    }),
+                    None,
+                )
+                .await
+            {
+                Ok(_) => get_metrics().increment("reconcile_image_removed"),
+                Err(e) => eprintln!("Failed to remove orphaned image {}: {}", image.id, e),
+            }
+        }
+        Ok(())
+    }
+
     async fn cleanup_containers() -> Result<(), Box<dyn std::error::Error>> {
-        let docker = Docker::connect_with_local_defaults()?;
-        println!("Connected to Docker successfully.");
+        let docker = get_docker_instance()?;
         let created_by_tag = get_global_config(|config| config.clone())
             .await
             .constants
@@ -2,14 +2,16 @@ use crate::{
     models::config_models::Config,
     services::{
         all_session_services::{
-            session_cache_service::SessionCache,
             session_management_service::SessionManagementService,
+            session_store_service::init_session_store,
         },
+        helper_services::language_registry_service::LanguageRegistry,
         websocket::websocket_sessionpool_service::ConnectionManager,
     },
 };
 use once_cell::sync::OnceCell;
 use std::fs;
+use std::time::Duration;
 use tokio::sync::Mutex;
 
 pub static GLOBAL_CONFIG: OnceCell<Mutex<Config>> = OnceCell::new();
@@ -25,10 +27,14 @@ impl Config {
     }
 
     pub fn init(&mut self) {
-        let session_mangement_service = SessionManagementService::new();
+        let language_registry = LanguageRegistry::new(&self.languages);
+        self.language_registry = Some(language_registry);
+        // Before the session services, which write through to the store
+        init_session_store(&self.session_store);
+        let session_mangement_service = SessionManagementService::new(Duration::from_secs(
+            self.session_configs.session_timeout,
+        ));
         self.session_management_service = Some(session_mangement_service);
-        let session_cache: &'static SessionCache = SessionCache::new();
-        self.session_cache_service = Some(session_cache);
         let websocket_connection_manager = ConnectionManager::get_connection_manager();
         self.websocket_seesion_pool = Some(websocket_connection_manager)
     }
@@ -39,9 +45,6 @@ impl Config {
     pub fn set_websocket_connection_manager(&mut self, wbcm: &'static ConnectionManager) {
         self.websocket_seesion_pool = Some(wbcm)
     }
-    pub fn set_session_cache(&mut self, session_cache: &'static SessionCache) {
-        self.session_cache_service = Some(session_cache)
-    }
 }
 
 pub async fn get_global_config<F, R>(f: F) -> R
@@ -56,15 +59,38 @@ where
     f(&*guard)
 }
 
-pub async fn get_global_config_mut() -> tokio::sync::MutexGuard<'static, Config> {
-    GLOBAL_CONFIG
-        .get()
-        .expect("Global config mutex not initialized")
-        .lock()
-        .await
-}
 pub fn set_global_config(config: Config) {
     GLOBAL_CONFIG
         .set(Mutex::new(config))
         .expect("Failed to set global config");
 }
+
+/// Loads `config.toml` as the global config, once per test binary, with the language registry
+/// but without the session services, which tests build for themselves.
+#[cfg(test)]
+pub fn init_test_config() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        let mut config = Config::new();
+        config.language_registry = Some(LanguageRegistry::new(&config.languages));
+        set_global_config(config);
+    });
+}
+
+/// `init_test_config` plus the session management service, for tests of handlers that reach
+/// sessions through the global config. A run that finds its session busy is rejected, so no
+/// test waits on another's run.
+#[cfg(test)]
+pub async fn init_test_sessions() -> &'static SessionManagementService {
+    init_test_config();
+    let sessions = SessionManagementService::new(Duration::from_secs(60));
+    let mut config = GLOBAL_CONFIG
+        .get()
+        .expect("Global config not initialized")
+        .lock()
+        .await;
+    config.set_session_management_service(sessions);
+    config.session_configs.concurrent_run_policy =
+        crate::models::config_models::ConcurrentRunPolicy::Reject;
+    sessions
+}
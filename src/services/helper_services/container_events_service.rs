@@ -0,0 +1,116 @@
+use bollard::{errors::Error as BollardError, system::EventsOptions};
+use futures_util::stream::StreamExt;
+use std::collections::HashSet;
+use std::error::Error;
+use std::time::Duration;
+
+use crate::{
+    services::{
+        all_session_services::session_management_service::{
+            SessionManagement, SessionManagementService,
+        },
+        helper_services::{config_service::get_global_config, metrics_service::get_metrics},
+    },
+    utils::docker_utils::get_docker_instance,
+};
+
+// Wait before resubscribing when the events stream ends, e.g. while the daemon restarts
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Follows the Docker events stream for containers this service created and records on the
+/// owning session why its container died or was removed. The next run of such a session then
+/// gets a new container or fails with `EnvironmentLost`, see
+/// `session_configs.recreate_lost_containers`. Never returns; resubscribes when the stream ends.
+pub async fn watch_container_events(sessions: &'static SessionManagementService) {
+    loop {
+        match follow_events(sessions).await {
+            Ok(()) => eprintln!("Container events stream ended, resubscribing"),
+            Err(e) => eprintln!("Container events stream failed, resubscribing: {}", e),
+        }
+        get_metrics().increment("container_events_disconnected");
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn follow_events(sessions: &SessionManagementService) -> Result<(), Box<dyn Error>> {
+    let docker = get_docker_instance()?;
+    let created_by_tag = get_global_config(|config| config.clone())
+        .await
+        .constants
+        .docker_created_by_label;
+    let mut events = docker.events(Some(EventsOptions::<String> {
+        filters: [
+            ("type".to_string(), vec!["container".to_string()]),
+            (
+                "event".to_string(),
+                vec!["oom".to_string(), "die".to_string(), "destroy".to_string()],
+            ),
+            ("label".to_string(), vec![created_by_tag]),
+        ]
+        .into_iter()
+        .collect(),
+        ..Default::default()
+    }));
+    println!("Watching container events");
+    // Docker reports an OOM kill as `oom` just before the container's `die`
+    let mut oom_killed = HashSet::new();
+    while let Some(event) = events.next().await {
+        let event = event?;
+        let (Some(action), Some(actor)) = (event.action, event.actor) else {
+            continue;
+        };
+        // The name is reused when a session's container is recreated, the id is not
+        let Some(id) = actor.id else {
+            continue;
+        };
+        let attributes = actor.attributes.unwrap_or_default();
+        let reason = match action.as_str() {
+            "oom" => {
+                oom_killed.insert(id);
+                continue;
+            }
+            "die" => exit_reason(
+                oom_killed.remove(&id),
+                attributes
+                    .get("exitCode")
+                    .and_then(|code| code.parse().ok()),
+            ),
+            "destroy" => {
+                oom_killed.remove(&id);
+                "container was removed".to_string()
+            }
+            _ => continue,
+        };
+        // Containers of sessions that already ended are torn down on purpose, ignore them
+        sessions.mark_environment_lost(&id, &reason).await;
+    }
+    Ok(())
+}
+
+/// Why the container `container` (a name or an id) is no longer running, or `None` while it
+/// runs (or can't be inspected). Used after a failed run, which may come before the events
+/// stream reports the death.
+pub async fn container_exit_reason(container: &str) -> Option<String> {
+    let docker = get_docker_instance().ok()?;
+    match docker.inspect_container(container, None).await {
+        Ok(inspected) => {
+            let state = inspected.state.unwrap_or_default();
+            if state.running == Some(true) {
+                return None;
+            }
+            Some(exit_reason(state.oom_killed == Some(true), state.exit_code))
+        }
+        Err(BollardError::DockerResponseServerError {
+            status_code: 404, ..
+        }) => Some("container was removed".to_string()),
+        Err(_) => None,
+    }
+}
+
+fn exit_reason(oom_killed: bool, exit_code: Option<i64>) -> String {
+    match (oom_killed, exit_code) {
+        (true, _) => "container was killed for running out of memory".to_string(),
+        (false, Some(code)) => format!("container exited with code {}", code),
+        (false, None) => "container exited".to_string(),
+    }
+}
@@ -0,0 +1,386 @@
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::str::FromStr;
+use tree_sitter::Tree;
+
+pub use crate::models::language_registry_models::{LanguageDefinition, LanguageRegistry};
+use crate::{
+    models::{
+        config_models::LanguageConfig,
+        docker_models::DockerSupportedLanguage,
+        validation_models::{EntryPoint, ValidationError},
+    },
+    services::validation_services::language_validation::{
+        get_validator, validator::SyntaxValidator,
+    },
+};
+
+static LANGUAGE_REGISTRY: OnceCell<LanguageRegistry> = OnceCell::new();
+
+impl LanguageRegistry {
+    pub fn new(languages: &HashMap<String, LanguageConfig>) -> &'static Self {
+        if LANGUAGE_REGISTRY.get().is_none() {
+            let mut aliases = HashMap::new();
+            let mut normalized = HashMap::new();
+            for (name, language_config) in languages {
+                let name = name.to_lowercase();
+                for alias in &language_config.aliases {
+                    aliases.insert(alias.to_lowercase(), name.clone());
+                }
+                normalized.insert(name, language_config.clone());
+            }
+            println!(
+                "Loaded {} languages into the registry: {:?}",
+                normalized.len(),
+                normalized.keys().collect::<Vec<_>>()
+            );
+            LANGUAGE_REGISTRY
+                .set(LanguageRegistry {
+                    languages: normalized,
+                    aliases,
+                })
+                .ok();
+        }
+        LANGUAGE_REGISTRY.get().unwrap()
+    }
+
+    /// Looks a language up by its name or one of its aliases (case-insensitive).
+    /// A version can be pinned with `name@version`; otherwise `default_version` is used.
+    pub fn resolve(&self, language: &str) -> Option<LanguageDefinition> {
+        let language = language.trim().to_lowercase();
+        let (language, version) = match language.split_once('@') {
+            Some((name, version)) => (name.to_string(), Some(version.to_string())),
+            None => (language, None),
+        };
+        let name = self.aliases.get(&language).unwrap_or(&language);
+        let language_config = self.languages.get(name)?;
+        let mut config = language_config.clone();
+        let version = match version.or_else(|| language_config.default_version.clone()) {
+            Some(version) if language_config.default_version.as_deref() == Some(&version) => {
+                Some(version)
+            }
+            Some(version) => {
+                let version_config = language_config.versions.get(&version)?;
+                config.dockerfile = version_config.dockerfile.clone();
+                config.image = version_config.image.clone();
+                Some(version)
+            }
+            None => None,
+        };
+        Some(LanguageDefinition {
+            name: name.clone(),
+            version,
+            config,
+        })
+    }
+
+    /// Same as `resolve`, but with the version passed separately (e.g. a request field).
+    pub fn resolve_version(
+        &self,
+        language: &str,
+        version: Option<&str>,
+    ) -> Option<LanguageDefinition> {
+        match version.map(str::trim).filter(|version| !version.is_empty()) {
+            Some(version) if !language.contains('@') => {
+                self.resolve(&format!("{}@{}", language, version))
+            }
+            _ => self.resolve(language),
+        }
+    }
+}
+
+pub fn get_language_registry() -> &'static LanguageRegistry {
+    LANGUAGE_REGISTRY
+        .get()
+        .expect("Language registry not initialized")
+}
+
+impl LanguageDefinition {
+    /// Canonical `name@version` string, used in session keys.
+    pub fn key(&self) -> String {
+        match &self.version {
+            Some(version) => format!("{}@{}", self.name, version),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Docker-safe form of `key()` for image tags and container names.
+    pub fn slug(&self) -> String {
+        self.key()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '_' => c,
+                _ => '-',
+            })
+            .collect()
+    }
+
+    /// Source file name; a detected entry point (e.g. a Java public class) overrides
+    /// the configured `source_file`.
+    pub fn source_file_name(&self, entry_point: Option<&EntryPoint>) -> String {
+        format!(
+            "{}.{}",
+            self.source_stem(entry_point),
+            self.config.file_extension
+        )
+    }
+
+    pub fn compile_command(&self, entry_point: Option<&EntryPoint>) -> Option<String> {
+        self.config
+            .compile_command
+            .as_ref()
+            .filter(|command| !command.trim().is_empty())
+            .map(|command| self.fill_placeholders(command, entry_point))
+    }
+
+    pub fn run_command(&self, entry_point: Option<&EntryPoint>) -> String {
+        self.fill_placeholders(&self.config.run_command, entry_point)
+    }
+
+    /// Finds the runnable entry point of `code` for grammars that need one.
+    pub fn entry_point(&self, code: &str) -> Result<Option<EntryPoint>, ValidationError> {
+        match self.validator() {
+            Some(validator) => validator.entry_point(code),
+            None => Ok(None),
+        }
+    }
+
+    /// Built-in grammar used for syntax validation, if the language declares one.
+    pub fn grammar(&self) -> Option<DockerSupportedLanguage> {
+        self.config
+            .grammar
+            .as_ref()
+            .and_then(|grammar| DockerSupportedLanguage::from_str(grammar).ok())
+    }
+
+    pub fn validator(&self) -> Option<Box<dyn SyntaxValidator>> {
+        self.grammar().map(get_validator)
+    }
+
+    /// Rejects imports that are neither standard library nor in the `packages` allow-list.
+    pub fn check_imports(&self, code: &str) -> Result<(), ValidationError> {
+        let validator = match self.validator() {
+            Some(validator) => validator,
+            None => return Ok(()),
+        };
+        let modules = validator.imports(code)?;
+        self.check_modules(validator.as_ref(), modules)
+    }
+
+    /// `check_imports` of code already parsed into `tree`.
+    pub fn check_tree_imports(&self, tree: &Tree, code: &str) -> Result<(), ValidationError> {
+        let validator = match self.validator() {
+            Some(validator) => validator,
+            None => return Ok(()),
+        };
+        let modules = validator.tree_imports(tree, code);
+        self.check_modules(validator.as_ref(), modules)
+    }
+
+    fn check_modules(
+        &self,
+        validator: &dyn SyntaxValidator,
+        modules: Vec<String>,
+    ) -> Result<(), ValidationError> {
+        let packages = &self.config.packages;
+        let importable: Vec<&str> = packages
+            .iter()
+            .flat_map(|package| match self.config.package_imports.get(package) {
+                Some(imports) => imports.iter().map(String::as_str).collect(),
+                None => vec![package.as_str()],
+            })
+            .collect();
+        for module in modules {
+            let allowed = validator.is_standard_library(&module)
+                || importable
+                    .iter()
+                    .any(|import| module == *import || module.starts_with(&format!("{}.", import)));
+            if !allowed {
+                let allowed_list = if packages.is_empty() {
+                    "none".to_string()
+                } else {
+                    packages.join(", ")
+                };
+                return Err(ValidationError::DisallowedPackage(format!(
+                    "'{}' is not available for {} (allowed third-party packages: {})",
+                    module,
+                    self.key(),
+                    allowed_list
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn source_stem(&self, entry_point: Option<&EntryPoint>) -> String {
+        entry_point
+            .map(|entry_point| entry_point.file_stem.clone())
+            .unwrap_or_else(|| self.config.source_file.clone())
+    }
+
+    fn fill_placeholders(&self, command: &str, entry_point: Option<&EntryPoint>) -> String {
+        let main = entry_point
+            .map(|entry_point| entry_point.main_symbol.clone())
+            .unwrap_or_else(|| self.source_stem(entry_point));
+        command
+            .replace("{file}", &self.source_file_name(entry_point))
+            .replace("{stem}", &self.source_stem(entry_point))
+            .replace("{main}", &main)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::helper_services::config_service::init_test_config;
+
+    // The configured language, allowing `packages`
+    fn language(name: &str, packages: &[&str]) -> LanguageDefinition {
+        init_test_config();
+        let mut language = get_language_registry().resolve(name).unwrap();
+        language.config.packages = packages.iter().map(|package| package.to_string()).collect();
+        language
+    }
+
+    fn resolved_key(language: &str, version: Option<&str>) -> Option<String> {
+        init_test_config();
+        get_language_registry()
+            .resolve_version(language, version)
+            .map(|language| language.key())
+    }
+
+    #[test]
+    fn a_bare_name_or_alias_resolves_to_the_default_version() {
+        assert_eq!(resolved_key("python", None).as_deref(), Some("python@3.9"));
+        assert_eq!(resolved_key(" PY ", None).as_deref(), Some("python@3.9"));
+        assert_eq!(
+            resolved_key("python@3.9", None).as_deref(),
+            Some("python@3.9")
+        );
+        assert_eq!(
+            resolved_key("python", Some(" ")).as_deref(),
+            Some("python@3.9")
+        );
+    }
+
+    #[test]
+    fn a_pinned_version_uses_its_own_image() {
+        init_test_config();
+        let pinned = get_language_registry().resolve("python@3.12").unwrap();
+        assert_eq!(pinned.key(), "python@3.12");
+        assert_eq!(
+            pinned.config.dockerfile.as_deref(),
+            Some("./docker/Dockerfile.python-3.12")
+        );
+        assert_eq!(
+            resolved_key("py", Some("3.12")).as_deref(),
+            Some("python@3.12")
+        );
+        // A version in the name wins over the separate one
+        assert_eq!(
+            resolved_key("python@3.12", Some("3.9")).as_deref(),
+            Some("python@3.12")
+        );
+    }
+
+    #[test]
+    fn unknown_languages_versions_and_malformed_keys_do_not_resolve() {
+        for (language, version) in [
+            ("cobol", None),
+            ("python@2.7", None),
+            ("python", Some("2.7")),
+            ("python@", None),
+            ("@3.12", None),
+            ("", None),
+        ] {
+            assert_eq!(
+                resolved_key(language, version),
+                None,
+                "{:?}",
+                (language, version)
+            );
+        }
+    }
+
+    fn disallowed(result: Result<(), ValidationError>) -> String {
+        match result {
+            Err(ValidationError::DisallowedPackage(message)) => message,
+            other => panic!("expected DisallowedPackage, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn only_allowed_packages_and_the_standard_library_may_be_imported() {
+        let python = language("python", &["numpy"]);
+        assert!(
+            python
+                .check_imports("import numpy as np\nimport os")
+                .is_ok()
+        );
+        assert!(
+            python
+                .check_imports("from collections import deque")
+                .is_ok()
+        );
+        assert!(
+            python
+                .check_imports("from numpy.linalg import norm")
+                .is_ok()
+        );
+        let message = disallowed(python.check_imports("import numpy\nimport pandas"));
+        assert!(message.contains("'pandas'"), "{}", message);
+        assert!(message.contains("numpy"), "{}", message);
+
+        let message = disallowed(language("python", &[]).check_imports("import numpy"));
+        assert!(message.contains("none"), "{}", message);
+    }
+
+    #[test]
+    fn packages_are_imported_under_their_mapped_names() {
+        let python = language("python", &["scikit-learn", "pyyaml"]);
+        assert!(
+            python
+                .check_imports("from sklearn.linear_model import LinearRegression\nimport yaml")
+                .is_ok()
+        );
+        // Only the mapped name is importable
+        disallowed(python.check_imports("import scikit_learn"));
+        disallowed(python.check_imports("import pyyaml"));
+    }
+
+    #[test]
+    fn nested_imports_are_checked() {
+        let python = language("python", &[]);
+        disallowed(python.check_imports("def solve():\n    import pandas\n"));
+    }
+
+    #[test]
+    fn java_imports_are_checked_by_package_prefix() {
+        let java = language("java", &["com.google.common"]);
+        assert!(
+            java.check_imports("import java.util.*;\nimport com.google.common.collect.*;")
+                .is_ok()
+        );
+        assert!(
+            java.check_imports("import com.google.common.collect.ImmutableList;")
+                .is_ok()
+        );
+        disallowed(java.check_imports("import org.apache.commons.*;"));
+        // A prefix only matches whole package names
+        disallowed(java.check_imports("import com.google.commonx.Tools;"));
+    }
+
+    #[test]
+    fn imports_of_a_parsed_tree_are_checked_the_same() {
+        let python = language("python", &["numpy"]);
+        let validator = python.validator().unwrap();
+        for (code, allowed) in [
+            ("import numpy\nfrom itertools import chain", true),
+            ("from pandas import DataFrame", false),
+        ] {
+            let tree = validator.validate(code).unwrap();
+            assert_eq!(python.check_tree_imports(&tree, code).is_ok(), allowed);
+            assert_eq!(python.check_imports(code).is_ok(), allowed);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use once_cell::sync::OnceCell;
+use std::time::Duration;
+
+pub use crate::models::metrics_models::Metrics;
+
+static METRICS: OnceCell<Metrics> = OnceCell::new();
+
+pub fn get_metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::default)
+}
+
+impl Metrics {
+    pub fn increment(&self, name: &str) {
+        *self.counters.entry(name.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn observe(&self, name: &str, duration: Duration) {
+        let millis = duration.as_millis();
+        let mut stat = self.timings.entry(name.to_string()).or_default();
+        stat.count += 1;
+        stat.total_ms += millis;
+        stat.max_ms = stat.max_ms.max(millis);
+    }
+
+    pub fn report(&self) -> String {
+        let mut lines: Vec<String> = self
+            .counters
+            .iter()
+            .map(|entry| format!("{} = {}", entry.key(), entry.value()))
+            .collect();
+        lines.extend(self.timings.iter().map(|entry| {
+            let stat = entry.value();
+            format!(
+                "{}: count={} avg_ms={} max_ms={}",
+                entry.key(),
+                stat.count,
+                stat.total_ms / stat.count.max(1) as u128,
+                stat.max_ms
+            )
+        }));
+        lines.sort();
+        lines.join("\n")
+    }
+}
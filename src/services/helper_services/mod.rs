@@ -1,6 +1,9 @@
 pub mod cleanup_service;
 
 pub mod config_service;
+pub mod container_events_service;
 pub mod docker_service;
+pub mod language_registry_service;
+pub mod metrics_service;
 
 pub mod ports_service;
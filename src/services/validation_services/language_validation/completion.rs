@@ -0,0 +1,319 @@
+use super::outline::definitions;
+use super::validator::SyntaxValidator;
+pub use crate::models::validation_models::{Completion, CompletionKind, SymbolKind};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::ops::Range;
+use tree_sitter::{Node, Tree};
+
+// Most suggestions returned for one request
+const MAX_COMPLETIONS: usize = 50;
+
+// Where a name was found, for ranking: identifiers from closer scopes first, then by distance
+// from the cursor; keywords and builtins after all of them
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Proximity {
+    source: u8, // 0 for the code's own identifiers, 1 for keywords and builtins
+    depth: Reverse<usize>,
+    distance: usize,
+}
+
+/// Suggestions for the word ending at byte `offset` of `code`, best first, and the byte offset
+/// where that word starts. Suggestions are the identifiers in scope at `offset`, the
+/// language's keywords and its builtins, whose name starts with the word (matching case
+/// first). Scopes are the definitions the outline finds: a name defined in a function is only
+/// suggested inside it. After a `.` only identifiers are suggested, from anywhere in the code.
+/// Nothing is suggested inside strings and comments.
+pub fn complete(
+    validator: &dyn SyntaxValidator,
+    tree: &Tree,
+    code: &str,
+    offset: usize,
+) -> (usize, Vec<Completion>) {
+    let start = word_start(code, offset);
+    let prefix = &code[start..offset];
+    if prefix.starts_with(|c: char| c.is_ascii_digit()) || in_string_or_comment(tree, offset) {
+        return (start, Vec::new());
+    }
+    let member = code[..start].ends_with('.');
+
+    let scopes: Vec<(Range<usize>, Range<usize>, SymbolKind)> = validator
+        .outline_query()
+        .map(|query| definitions(query, tree, code))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|definition| (definition.range, definition.name_range, definition.kind))
+        .collect();
+    let mut candidates: HashMap<&str, (CompletionKind, Proximity)> = HashMap::new();
+    let mut identifiers = Vec::new();
+    collect_identifiers(tree.root_node(), &mut identifiers);
+    for identifier in identifiers {
+        let range = identifier.byte_range();
+        // The word being typed
+        if range.start <= start && offset <= range.end {
+            continue;
+        }
+        let Ok(name) = identifier.utf8_text(code.as_bytes()) else {
+            continue;
+        };
+        let definition = scopes
+            .iter()
+            .find(|(_, name_range, _)| *name_range == range);
+        // Definitions enclosing the identifier; a definition's own name belongs to the scope
+        // around it
+        let enclosing: Vec<&Range<usize>> = scopes
+            .iter()
+            .map(|(scope, _, _)| scope)
+            .filter(|scope| scope.start <= range.start && range.end <= scope.end)
+            .filter(|scope| definition.is_none_or(|(own, _, _)| *scope != own))
+            .collect();
+        let in_scope = enclosing
+            .iter()
+            .all(|scope| scope.start <= offset && offset <= scope.end);
+        if !member && !in_scope {
+            continue;
+        }
+        let kind = match definition {
+            Some((_, _, SymbolKind::Class | SymbolKind::Interface | SymbolKind::Enum)) => {
+                CompletionKind::Class
+            }
+            Some(_) => CompletionKind::Function,
+            None => CompletionKind::Variable,
+        };
+        let proximity = Proximity {
+            source: 0,
+            depth: Reverse(enclosing.len()),
+            distance: range.start.abs_diff(offset),
+        };
+        match candidates.entry(name) {
+            Entry::Vacant(entry) => {
+                entry.insert((kind, proximity));
+            }
+            Entry::Occupied(mut entry) => {
+                let (best_kind, best) = entry.get_mut();
+                if *best_kind == CompletionKind::Variable {
+                    *best_kind = kind;
+                }
+                *best = (*best).min(proximity);
+            }
+        }
+    }
+    if !member {
+        let words = validator
+            .keywords()
+            .iter()
+            .map(|word| (*word, CompletionKind::Keyword))
+            .chain(
+                validator
+                    .builtins()
+                    .iter()
+                    .map(|word| (*word, CompletionKind::Builtin)),
+            );
+        for (word, kind) in words {
+            candidates.entry(word).or_insert((
+                kind,
+                Proximity {
+                    source: 1,
+                    depth: Reverse(0),
+                    distance: 0,
+                },
+            ));
+        }
+    }
+
+    let lowercase_prefix = prefix.to_lowercase();
+    let mut matches: Vec<_> = candidates
+        .into_iter()
+        .filter(|(name, _)| *name != prefix)
+        .filter_map(|(name, (kind, proximity))| {
+            let case_mismatch = if name.starts_with(prefix) {
+                false
+            } else if name.to_lowercase().starts_with(&lowercase_prefix) {
+                true
+            } else {
+                return None;
+            };
+            Some(((case_mismatch, proximity, name.len(), name), kind))
+        })
+        .collect();
+    matches.sort_unstable_by_key(|(key, _)| *key);
+    let completions = matches
+        .into_iter()
+        .take(MAX_COMPLETIONS)
+        .map(|((_, _, _, name), kind)| Completion {
+            label: name.to_string(),
+            kind,
+        })
+        .collect();
+    (start, completions)
+}
+
+// Start of the identifier characters right before `offset`
+fn word_start(code: &str, offset: usize) -> usize {
+    code[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_alphanumeric() || *c == '_' || *c == '$')
+        .last()
+        .map_or(offset, |(index, _)| index)
+}
+
+fn in_string_or_comment(tree: &Tree, offset: usize) -> bool {
+    // The smallest node holding the character before the cursor
+    let mut node = tree
+        .root_node()
+        .descendant_for_byte_range(offset.saturating_sub(1), offset);
+    while let Some(current) = node {
+        let kind = current.kind();
+        // Code inside a template literal's `${...}`
+        if kind == "template_substitution" {
+            return false;
+        }
+        // A line comment runs to the end of the line, the cursor included
+        if (kind.contains("comment")
+            && current.start_byte() < offset
+            && offset <= current.end_byte())
+            || (kind.contains("string")
+                && current.start_byte() < offset
+                && offset < current.end_byte())
+        {
+            return true;
+        }
+        node = current.parent();
+    }
+    false
+}
+
+// Identifier tokens: `identifier` and kinds such as `property_identifier` or `type_identifier`,
+// but not composites like Java's `scoped_identifier`
+fn collect_identifiers<'tree>(node: Node<'tree>, identifiers: &mut Vec<Node<'tree>>) {
+    if node.child_count() == 0 {
+        let kind = node.kind();
+        if (kind == "identifier" || kind.ends_with("_identifier")) && !node.is_missing() {
+            identifiers.push(node);
+        }
+        return;
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_identifiers(child, identifiers);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        java_validator::JavaValidator, javascipt_validator::JavaScriptValidator,
+        python_validator::PythonValidator,
+    };
+    use super::*;
+
+    // Completions at the `|` in `code`, and where the word being completed starts
+    fn complete_at(validator: &dyn SyntaxValidator, code: &str) -> (usize, Vec<String>) {
+        let offset = code.find('|').unwrap();
+        let code = code.replacen('|', "", 1);
+        let tree = validator.parse(&code).unwrap();
+        let (start, completions) = complete(validator, &tree, &code, offset);
+        let labels = completions
+            .into_iter()
+            .map(|completion| completion.label)
+            .collect();
+        (start, labels)
+    }
+
+    fn suggestions(validator: &dyn SyntaxValidator, code: &str) -> Vec<String> {
+        complete_at(validator, code).1
+    }
+
+    #[test]
+    fn locals_of_other_functions_are_not_suggested() {
+        let code = "\
+def first():
+    counter = 1
+    return counter
+
+def second(count_limit):
+    co|
+";
+        let labels = suggestions(&PythonValidator, code);
+        assert!(!labels.contains(&"counter".to_string()), "{:?}", labels);
+        assert_eq!(labels[0], "count_limit");
+        assert!(labels.contains(&"continue".to_string()), "{:?}", labels);
+
+        // A function's own name belongs to the scope around it
+        let code = "function helper() {\n  const hidden = 1;\n}\nhe|";
+        assert_eq!(suggestions(&JavaScriptValidator, code), ["helper"]);
+    }
+
+    #[test]
+    fn nearer_scopes_rank_first() {
+        let code = "\
+total_module = 0
+
+def outer():
+    total_outer = 1
+    def inner():
+        total_inner = 2
+        return to|
+";
+        let (start, labels) = complete_at(&PythonValidator, code);
+        assert_eq!(&labels[..3], ["total_inner", "total_outer", "total_module"]);
+        assert_eq!(start, code.find("to|").unwrap());
+    }
+
+    #[test]
+    fn case_matches_come_first() {
+        // The case-insensitive match is closer to the cursor, and still comes after
+        let code = "value_min = 0\nVALUE_MAX = 9\nprint(va|)";
+        let labels = suggestions(&PythonValidator, code);
+        assert_eq!(
+            &labels[..],
+            ["value_min", "vars", "VALUE_MAX", "ValueError"]
+        );
+    }
+
+    #[test]
+    fn nothing_is_suggested_in_strings_or_comments() {
+        let py = &PythonValidator as &dyn SyntaxValidator;
+        let js = &JavaScriptValidator as &dyn SyntaxValidator;
+        let java = &JavaValidator as &dyn SyntaxValidator;
+        for (validator, code) in [
+            (py, "print_me = 1\ntext = 'pri|'\n"),
+            (py, "print_me = 1\n# pri|\n"),
+            (py, "print_me = 1\n\"\"\"\npri|\n\"\"\"\n"),
+            (js, "const count = 1;\nconst s = \"co|\";\n"),
+            (js, "const count = 1;\n// co|"),
+            (js, "const count = 1;\nconst t = `co|`;\n"),
+            (java, "class A {\n    int count;\n    /* co| */\n}\n"),
+            (java, "class A {\n    String s = \"co|\";\n}\n"),
+        ] {
+            assert!(suggestions(validator, code).is_empty(), "in {:?}", code);
+        }
+        // Code inside a template literal is completed
+        let code = "const count = 1;\nconst t = `${co|}`;\n";
+        assert!(suggestions(js, code).contains(&"count".to_string()));
+        // Right after a string or comment, too
+        assert!(
+            suggestions(py, "print_me = 1\nx = 'a' + pri|\n").contains(&"print_me".to_string())
+        );
+    }
+
+    #[test]
+    fn only_identifiers_are_suggested_after_a_dot() {
+        let code = "\
+class Basket:
+    def add_item(self, item):
+        self.items = [item]
+
+def fill(basket):
+    basket.a|
+";
+        // `add_item` is out of scope here but may be the member; `and`, `abs` and `all` aren't
+        let labels = suggestions(&PythonValidator, code);
+        assert_eq!(labels, ["add_item"]);
+        // Not `this`, `true`, `try`, `typeof` or `throw` either
+        let code = "const total = 1;\nconst obj = {};\nobj.t|";
+        assert_eq!(suggestions(&JavaScriptValidator, code), ["total"]);
+    }
+}
@@ -1,33 +1,216 @@
-use super::validator::{SyntaxValidator, ValidationError};
-use tree_sitter::Parser;
+use super::keywords::{JAVA_KEYWORDS, JAVA_STANDARD_NAMES};
+use super::outline::compile_query;
+use super::stdlib_modules::JAVA_STDLIB_PREFIXES;
+use super::validator::{EntryPoint, SyntaxValidator, ValidationError, parse_with};
+use once_cell::sync::OnceCell;
+use tree_sitter::{Node, Query, Tree};
 use tree_sitter_java;
 
 pub struct JavaValidator;
 
+// Definitions listed in the outline
+const OUTLINE_QUERY: &str = r#"
+(class_declaration name: (identifier) @name) @class
+(record_declaration name: (identifier) @name) @class
+(interface_declaration name: (identifier) @name) @interface
+(annotation_type_declaration name: (identifier) @name) @interface
+(enum_declaration name: (identifier) @name) @enum
+(method_declaration name: (identifier) @name) @method
+(constructor_declaration name: (identifier) @name) @constructor
+"#;
+static OUTLINE: OnceCell<Query> = OnceCell::new();
+
 impl SyntaxValidator for JavaValidator {
-    fn validate(&self, code: &str) -> Result<tree_sitter::Tree, ValidationError> {
-        // SAFETY: tree_sitter_java is an FFI call and must be marked unsafe.
-        let language = tree_sitter_java::language();
-
-        let mut parser = Parser::new();
-        println!("{} code received", code);
-        parser
-            .set_language(language)
-            .map_err(|e| self.validation_error(&format!("Set lang error: {:?}", e)))?;
-
-        let tree = parser
-            .parse(code, None)
-            .ok_or(self.validation_error("Failed to parse Java code"))?;
-
-        let root_node = tree.root_node();
-        if root_node.has_error() {
-            let error_message = format!(
-                "Syntax error detected in code at byte range {:?}",
-                root_node.to_sexp()
-            );
-            return Err(self.validation_error(&error_message));
-        }
-
-        Ok(tree)
+    fn reparse(&self, code: &str, old_tree: Option<&Tree>) -> Result<Tree, ValidationError> {
+        parse_with(tree_sitter_java::language(), code, old_tree)
+            .map_err(|e| self.validation_error(&format!("Set lang error: {:?}", e)))?
+            .ok_or(self.validation_error("Failed to parse Java code"))
+    }
+
+    fn entry_point(&self, code: &str) -> Result<Option<EntryPoint>, ValidationError> {
+        let tree = self.validate(code)?;
+        Self::find_entry_point(&tree, code).map(Some)
+    }
+
+    /// Fully qualified imports, e.g. `java.util.List` or `com.google.common.collect`.
+    fn tree_imports(&self, tree: &Tree, code: &str) -> Vec<String> {
+        let root = tree.root_node();
+        let mut cursor = root.walk();
+        root.children(&mut cursor)
+            .filter(|node| node.kind() == "import_declaration")
+            .filter_map(|import| {
+                let mut import_cursor = import.walk();
+                let name = import
+                    .named_children(&mut import_cursor)
+                    .find(|node| matches!(node.kind(), "scoped_identifier" | "identifier"))?;
+                name.utf8_text(code.as_bytes()).ok().map(str::to_string)
+            })
+            .collect()
+    }
+
+    fn is_standard_library(&self, module: &str) -> bool {
+        JAVA_STDLIB_PREFIXES
+            .iter()
+            .any(|prefix| module.starts_with(prefix))
+    }
+
+    fn outline_query(&self) -> Option<&'static Query> {
+        Some(OUTLINE.get_or_init(|| compile_query(tree_sitter_java::language(), OUTLINE_QUERY)))
+    }
+
+    fn keywords(&self) -> &'static [&'static str] {
+        JAVA_KEYWORDS
+    }
+
+    fn builtins(&self) -> &'static [&'static str] {
+        JAVA_STANDARD_NAMES
+    }
+}
+
+impl JavaValidator {
+    /// Text of the `package` declaration, if the file has one.
+    fn package_name(root: Node, code: &str) -> Option<String> {
+        let mut cursor = root.walk();
+        let package = root
+            .children(&mut cursor)
+            .find(|node| node.kind() == "package_declaration")?;
+        let mut package_cursor = package.walk();
+        let name = package
+            .named_children(&mut package_cursor)
+            .find(|node| matches!(node.kind(), "scoped_identifier" | "identifier"))?;
+        name.utf8_text(code.as_bytes()).ok().map(str::to_string)
+    }
+
+    fn has_modifier(node: Node, code: &str, modifier: &str) -> bool {
+        let mut cursor = node.walk();
+        node.children(&mut cursor)
+            .filter(|child| child.kind() == "modifiers")
+            .any(|modifiers| {
+                modifiers
+                    .utf8_text(code.as_bytes())
+                    .map(|text| text.split_whitespace().any(|word| word == modifier))
+                    .unwrap_or(false)
+            })
+    }
+
+    /// `public static void main(...)` declared directly in the class body.
+    fn has_main_method(class: Node, code: &str) -> bool {
+        let body = match class.child_by_field_name("body") {
+            Some(body) => body,
+            None => return false,
+        };
+        let mut cursor = body.walk();
+        body.named_children(&mut cursor).any(|member| {
+            member.kind() == "method_declaration"
+                && member
+                    .child_by_field_name("name")
+                    .and_then(|name| name.utf8_text(code.as_bytes()).ok())
+                    == Some("main")
+                && Self::has_modifier(member, code, "public")
+                && Self::has_modifier(member, code, "static")
+        })
+    }
+
+    fn find_entry_point(tree: &Tree, code: &str) -> Result<EntryPoint, ValidationError> {
+        let root = tree.root_node();
+        let mut cursor = root.walk();
+        let classes: Vec<(String, bool, bool)> = root
+            .children(&mut cursor)
+            .filter(|node| node.kind() == "class_declaration")
+            .filter_map(|class| {
+                let name = class
+                    .child_by_field_name("name")?
+                    .utf8_text(code.as_bytes())
+                    .ok()?
+                    .to_string();
+                Some((
+                    name,
+                    Self::has_modifier(class, code, "public"),
+                    Self::has_main_method(class, code),
+                ))
+            })
+            .collect();
+
+        // javac requires the file to be named after the public class, if there is one.
+        let public_class = classes.iter().find(|(_, is_public, _)| *is_public);
+        let main_class = classes
+            .iter()
+            .find(|(_, is_public, has_main)| *is_public && *has_main)
+            .or_else(|| classes.iter().find(|(_, _, has_main)| *has_main));
+
+        let (main_class, _, _) = main_class.ok_or_else(|| {
+            ValidationError::MissingEntryPoint(
+                "no class declares `public static void main(String[] args)`".to_string(),
+            )
+        })?;
+        let file_stem = public_class.map(|(name, _, _)| name).unwrap_or(main_class);
+        let main_symbol = match Self::package_name(root, code) {
+            Some(package) => format!("{}.{}", package, main_class),
+            None => main_class.clone(),
+        };
+        Ok(EntryPoint {
+            file_stem: file_stem.clone(),
+            main_symbol,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry_point(code: &str) -> Result<Option<EntryPoint>, ValidationError> {
+        JavaValidator.entry_point(code)
+    }
+
+    fn expected(file_stem: &str, main_symbol: &str) -> Option<EntryPoint> {
+        Some(EntryPoint {
+            file_stem: file_stem.to_string(),
+            main_symbol: main_symbol.to_string(),
+        })
+    }
+
+    const MAIN: &str = "public static void main(String[] args) {}";
+
+    #[test]
+    fn a_public_class_with_main_is_the_entry_point() {
+        let code = format!("public class Solution {{ {} }}", MAIN);
+        assert_eq!(
+            entry_point(&code).unwrap(),
+            expected("Solution", "Solution")
+        );
+    }
+
+    #[test]
+    fn the_package_qualifies_the_main_class() {
+        let code = format!("package com.x;\n\npublic class Solution {{ {} }}", MAIN);
+        assert_eq!(
+            entry_point(&code).unwrap(),
+            expected("Solution", "com.x.Solution")
+        );
+    }
+
+    #[test]
+    fn a_public_class_without_main_is_not_runnable() {
+        let code = "public class Solution { void main(String[] args) {} }";
+        assert!(matches!(
+            entry_point(code),
+            Err(ValidationError::MissingEntryPoint(_))
+        ));
+    }
+
+    #[test]
+    fn the_file_is_named_after_the_public_class_when_another_has_main() {
+        let code = format!(
+            "package com.x;\n\npublic class Solution {{}}\nclass Helper {{}}\nclass Runner {{ {} }}",
+            MAIN
+        );
+        assert_eq!(
+            entry_point(&code).unwrap(),
+            expected("Solution", "com.x.Runner")
+        );
+        // Without a public class, the file is named after the class with main
+        let code = format!("class Helper {{}}\nclass Runner {{ {} }}", MAIN);
+        assert_eq!(entry_point(&code).unwrap(), expected("Runner", "Runner"));
     }
 }
@@ -1,5 +1,9 @@
-use super::validator::{SyntaxValidator, ValidationError};
-use tree_sitter::Parser;
+use super::keywords::{JAVASCRIPT_GLOBALS, JAVASCRIPT_KEYWORDS};
+use super::outline::compile_query;
+use super::stdlib_modules::NODE_BUILTIN_MODULES;
+use super::validator::{SyntaxValidator, ValidationError, parse_with};
+use once_cell::sync::OnceCell;
+use tree_sitter::{Node, Query, Tree};
 use tree_sitter_javascript;
 
 // unsafe extern "C" {
@@ -8,28 +12,79 @@ use tree_sitter_javascript;
 
 pub struct JavaScriptValidator;
 
+// Definitions listed in the outline
+const OUTLINE_QUERY: &str = r#"
+(class_declaration name: (identifier) @name) @class
+(function_declaration name: (identifier) @name) @function
+(generator_function_declaration name: (identifier) @name) @function
+(method_definition name: (_) @name) @method
+(variable_declarator
+  name: (identifier) @name
+  value: [(arrow_function) (function_expression) (generator_function)]) @function
+"#;
+static OUTLINE: OnceCell<Query> = OnceCell::new();
+
 impl SyntaxValidator for JavaScriptValidator {
-    fn validate(&self, code: &str) -> Result<tree_sitter::Tree, ValidationError> {
-        let language = tree_sitter_javascript::language();
-        let mut parser = Parser::new();
-        println!("{} code received", code);
-        parser
-            .set_language(language)
-            .map_err(|e| self.validation_error(&format!("Set lang error: {:?}", e)))?;
-
-        let tree = parser
-            .parse(code, None)
-            .ok_or(self.validation_error("Failed to parse Python code"))?;
-
-        let root_node = tree.root_node();
-        if root_node.has_error() {
-            let error_message = format!(
-                "Syntax error detected in code at byte range {:?}",
-                root_node.to_sexp()
-            );
-            return Err(self.validation_error(&error_message));
-        }
+    fn reparse(&self, code: &str, old_tree: Option<&Tree>) -> Result<Tree, ValidationError> {
+        parse_with(tree_sitter_javascript::language(), code, old_tree)
+            .map_err(|e| self.validation_error(&format!("Set lang error: {:?}", e)))?
+            .ok_or(self.validation_error("Failed to parse JavaScript code"))
+    }
+
+    fn tree_imports(&self, tree: &Tree, code: &str) -> Vec<String> {
+        let mut modules = Vec::new();
+        collect_imports(tree.root_node(), code, &mut modules);
+        modules
+    }
+
+    fn is_standard_library(&self, module: &str) -> bool {
+        NODE_BUILTIN_MODULES.contains(&module)
+    }
+
+    fn outline_query(&self) -> Option<&'static Query> {
+        Some(
+            OUTLINE
+                .get_or_init(|| compile_query(tree_sitter_javascript::language(), OUTLINE_QUERY)),
+        )
+    }
 
-        Ok(tree)
+    fn keywords(&self) -> &'static [&'static str] {
+        JAVASCRIPT_KEYWORDS
+    }
+
+    fn builtins(&self) -> &'static [&'static str] {
+        JAVASCRIPT_GLOBALS
+    }
+}
+
+/// Package names from `import ... from "pkg"` and `require("pkg")`.
+/// Relative paths are skipped; `lodash/fp` becomes `lodash`, `@scope/pkg/x` becomes `@scope/pkg`.
+fn collect_imports(node: Node, code: &str, modules: &mut Vec<String>) {
+    let specifier = match node.kind() {
+        "import_statement" => node.child_by_field_name("source"),
+        "call_expression" => {
+            let is_require = node
+                .child_by_field_name("function")
+                .and_then(|function| function.utf8_text(code.as_bytes()).ok())
+                == Some("require");
+            node.child_by_field_name("arguments")
+                .filter(|_| is_require)
+                .and_then(|arguments| arguments.named_child(0))
+                .filter(|argument| argument.kind() == "string")
+        }
+        _ => None,
+    };
+    if let Some(specifier) = specifier.and_then(|node| node.utf8_text(code.as_bytes()).ok()) {
+        let specifier = specifier.trim_matches(|c| c == '"' || c == '\'' || c == '`');
+        let specifier = specifier.strip_prefix("node:").unwrap_or(specifier);
+        if !specifier.starts_with('.') && !specifier.starts_with('/') {
+            let segments = if specifier.starts_with('@') { 2 } else { 1 };
+            let package: Vec<&str> = specifier.split('/').take(segments).collect();
+            modules.push(package.join("/"));
+        }
+    }
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        collect_imports(child, code, modules);
     }
 }
@@ -1,6 +1,10 @@
+pub mod completion;
 pub mod java_validator;
 pub mod javascipt_validator;
+pub mod keywords;
+pub mod outline;
 pub mod python_validator;
+pub mod stdlib_modules;
 pub mod validator;
 
 use crate::models::docker_models::DockerSupportedLanguage as LanguageType;
@@ -0,0 +1,255 @@
+use super::validator::position_at;
+pub use crate::models::validation_models::{OutlineSymbol, SymbolKind};
+use std::collections::HashSet;
+use std::iter::Peekable;
+use std::ops::Range;
+use tree_sitter::{Language, Query, QueryCursor, Tree};
+
+/// Compiles one of the built-in outline queries. They are fixed at build time, so an invalid
+/// one is a bug.
+pub fn compile_query(language: Language, source: &str) -> Query {
+    Query::new(language, source)
+        .unwrap_or_else(|e| panic!("Invalid built-in outline query: {:?}", e))
+}
+
+// A definition matched by an outline query; ranges are byte offsets
+pub struct Definition {
+    pub kind: SymbolKind,
+    pub name: String,
+    pub range: Range<usize>,
+    pub name_range: Range<usize>,
+}
+
+/// The definitions `query` finds in `tree`, nested by where they are defined. Each pattern
+/// captures the definition as `@class`, `@interface`, `@enum`, `@function`, `@method` or
+/// `@constructor`, and its name as `@name`. A function defined directly in a class is
+/// reported as a method.
+pub fn outline(query: &Query, tree: &Tree, code: &str) -> Vec<OutlineSymbol> {
+    let symbols = definitions(query, tree, code)
+        .into_iter()
+        .map(|definition| {
+            let symbol = OutlineSymbol {
+                kind: definition.kind,
+                name: definition.name,
+                range: (
+                    position_at(code, definition.range.start),
+                    position_at(code, definition.range.end),
+                ),
+                children: Vec::new(),
+            };
+            (definition.range, symbol)
+        });
+    nest(&mut symbols.peekable(), usize::MAX, None)
+}
+
+/// The definitions `query` finds in `tree`, flat and ordered by start; a definition comes
+/// before the ones inside it.
+pub fn definitions(query: &Query, tree: &Tree, code: &str) -> Vec<Definition> {
+    let mut cursor = QueryCursor::new();
+    let mut seen = HashSet::new();
+    let mut definitions = Vec::new();
+    for found in cursor.matches(query, tree.root_node(), code.as_bytes()) {
+        let mut definition = None;
+        let mut name = None;
+        for capture in found.captures {
+            match query.capture_names()[capture.index as usize].as_str() {
+                "name" => name = Some(capture.node),
+                capture_name => {
+                    definition = symbol_kind(capture_name).map(|kind| (kind, capture.node))
+                }
+            }
+        }
+        let (Some((kind, node)), Some(name)) = (definition, name) else {
+            continue;
+        };
+        // Several patterns can match one definition; the first wins
+        if !seen.insert(node.id()) {
+            continue;
+        }
+        let Ok(name_text) = name.utf8_text(code.as_bytes()) else {
+            continue;
+        };
+        definitions.push(Definition {
+            kind,
+            name: name_text.to_string(),
+            range: node.byte_range(),
+            name_range: name.byte_range(),
+        });
+    }
+    definitions.sort_by_key(|definition| {
+        (
+            definition.range.start,
+            std::cmp::Reverse(definition.range.end),
+        )
+    });
+    definitions
+}
+
+fn symbol_kind(capture_name: &str) -> Option<SymbolKind> {
+    match capture_name {
+        "class" => Some(SymbolKind::Class),
+        "interface" => Some(SymbolKind::Interface),
+        "enum" => Some(SymbolKind::Enum),
+        "function" => Some(SymbolKind::Function),
+        "method" => Some(SymbolKind::Method),
+        "constructor" => Some(SymbolKind::Constructor),
+        _ => None,
+    }
+}
+
+// Takes the definitions, sorted by start, that begin before `end` (the end of the enclosing
+// definition, of kind `parent`), each with the definitions inside it as children
+fn nest(
+    definitions: &mut Peekable<impl Iterator<Item = (Range<usize>, OutlineSymbol)>>,
+    end: usize,
+    parent: Option<SymbolKind>,
+) -> Vec<OutlineSymbol> {
+    let mut symbols = Vec::new();
+    while let Some((range, mut symbol)) = definitions.next_if(|(range, _)| range.start < end) {
+        if symbol.kind == SymbolKind::Function && parent == Some(SymbolKind::Class) {
+            symbol.kind = SymbolKind::Method;
+        }
+        symbol.children = nest(definitions, range.end, Some(symbol.kind));
+        symbols.push(symbol);
+    }
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        java_validator::JavaValidator, javascipt_validator::JavaScriptValidator,
+        python_validator::PythonValidator, validator::SyntaxValidator,
+    };
+    use super::*;
+
+    // One line per symbol: kind, name and range as line:ch, indented under its parent
+    fn render(symbols: &[OutlineSymbol], depth: usize, lines: &mut Vec<String>) {
+        for symbol in symbols {
+            let (start, end) = symbol.range;
+            lines.push(format!(
+                "{}{:?} {} {}:{}-{}:{}",
+                "  ".repeat(depth),
+                symbol.kind,
+                symbol.name,
+                start.line,
+                start.ch,
+                end.line,
+                end.ch
+            ));
+            render(&symbol.children, depth + 1, lines);
+        }
+    }
+
+    fn outline_of(validator: &dyn SyntaxValidator, code: &str) -> Vec<String> {
+        let tree = validator.parse(code).unwrap();
+        let symbols = outline(validator.outline_query().unwrap(), &tree, code);
+        let mut lines = Vec::new();
+        render(&symbols, 0, &mut lines);
+        lines
+    }
+
+    #[test]
+    fn python_outline() {
+        let code = "\
+class Grüße:
+    def __init__(self, name):
+        self.name = name
+
+    def greet(self):
+        def inner():
+            return '😀'
+        return inner()
+
+    class Inner:
+        pass
+
+def main():
+    pass
+";
+        // Functions defined in a class are methods; functions defined in those are not
+        assert_eq!(
+            outline_of(&PythonValidator, code),
+            [
+                "Class Grüße 0:0-10:12",
+                "  Method __init__ 1:4-2:24",
+                "  Method greet 4:4-7:22",
+                "    Function inner 5:8-6:23",
+                "  Class Inner 9:4-10:12",
+                "Function main 12:0-13:8",
+            ]
+        );
+    }
+
+    #[test]
+    fn javascript_outline() {
+        let code = "\
+class Shape {
+  constructor(w) { this.w = w; }
+  area() { return 0; }
+}
+function helper() {
+  const inner = () => 1;
+}
+const arrow = () => '😀';
+const fn = function () {};
+function* gen() {}
+let value = 3;
+";
+        // Variables count only when they hold a function, ranged over the declarator
+        assert_eq!(
+            outline_of(&JavaScriptValidator, code),
+            [
+                "Class Shape 0:0-3:1",
+                "  Method constructor 1:2-1:32",
+                "  Method area 2:2-2:22",
+                "Function helper 4:0-6:1",
+                "  Function inner 5:8-5:23",
+                "Function arrow 7:6-7:24",
+                "Function fn 8:6-8:25",
+                "Function gen 9:0-9:18",
+            ]
+        );
+    }
+
+    #[test]
+    fn java_outline() {
+        let code = "\
+public class Main {
+    private int x;
+    public Main() {}
+    void run() {}
+    interface Shape { double area(); }
+    enum Color { RED }
+    record Point(int x, int y) {}
+    @interface Tag {}
+}
+";
+        // Records are classes and annotation types interfaces; fields aren't listed
+        assert_eq!(
+            outline_of(&JavaValidator, code),
+            [
+                "Class Main 0:0-8:1",
+                "  Constructor Main 2:4-2:20",
+                "  Method run 3:4-3:17",
+                "  Interface Shape 4:4-4:38",
+                "    Method area 4:22-4:36",
+                "  Enum Color 5:4-5:22",
+                "  Class Point 6:4-6:33",
+                "  Interface Tag 7:4-7:21",
+            ]
+        );
+    }
+
+    #[test]
+    fn an_outline_of_code_with_syntax_errors_keeps_what_parsed() {
+        let code = "def ok():\n    pass\n\ndef broken(:\n    pass\n\nclass After:\n    def m(self): pass\n";
+        let lines = outline_of(&PythonValidator, code);
+        assert_eq!(
+            lines.first().map(String::as_str),
+            Some("Function ok 0:0-1:8")
+        );
+        assert!(lines.iter().any(|line| line.starts_with("Class After 6:0")));
+        assert!(lines.iter().any(|line| line.starts_with("  Method m 7:4")));
+    }
+}
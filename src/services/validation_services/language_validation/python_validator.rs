@@ -1,32 +1,121 @@
-use super::validator::{SyntaxValidator, ValidationError};
-use tree_sitter::Parser;
+use super::keywords::{PYTHON_BUILTINS, PYTHON_KEYWORDS};
+use super::outline::compile_query;
+use super::stdlib_modules::PYTHON_STDLIB_MODULES;
+use super::validator::{SyntaxValidator, ValidationError, parse_with};
+use once_cell::sync::OnceCell;
+use tree_sitter::{Node, Query, Tree};
 use tree_sitter_python;
 pub struct PythonValidator;
 
+// Definitions listed in the outline
+const OUTLINE_QUERY: &str = r#"
+(class_definition name: (identifier) @name) @class
+(function_definition name: (identifier) @name) @function
+"#;
+static OUTLINE: OnceCell<Query> = OnceCell::new();
+
 impl SyntaxValidator for PythonValidator {
-    fn validate(&self, code: &str) -> Result<tree_sitter::Tree, ValidationError> {
-        let language = tree_sitter_python::language();
-        let mut parser = Parser::new();
-        println!("{} code received", code);
-        parser
-            .set_language(language)
-            .map_err(|e| self.validation_error(&format!("Set lang error: {:?}", e)))?;
-
-        let tree = parser
-            .parse(code, None)
-            .ok_or(self.validation_error("Failed to parse Python code"))?;
-
-        // Check for error nodes in the tree
-        let root_node = tree.root_node();
-        if root_node.has_error() {
-            let error_message = format!(
-                "Syntax error detected in code at byte range {:?}",
-                root_node.to_sexp()
-            );
-
-            return Err(self.validation_error(&error_message));
+    fn reparse(&self, code: &str, old_tree: Option<&Tree>) -> Result<Tree, ValidationError> {
+        parse_with(tree_sitter_python::language(), code, old_tree)
+            .map_err(|e| self.validation_error(&format!("Set lang error: {:?}", e)))?
+            .ok_or(self.validation_error("Failed to parse Python code"))
+    }
+
+    fn tree_imports(&self, tree: &Tree, code: &str) -> Vec<String> {
+        let mut modules = Vec::new();
+        collect_imports(tree.root_node(), code, &mut modules);
+        modules
+    }
+
+    fn is_standard_library(&self, module: &str) -> bool {
+        PYTHON_STDLIB_MODULES.contains(&module)
+    }
+
+    fn outline_query(&self) -> Option<&'static Query> {
+        Some(OUTLINE.get_or_init(|| compile_query(tree_sitter_python::language(), OUTLINE_QUERY)))
+    }
+
+    fn keywords(&self) -> &'static [&'static str] {
+        PYTHON_KEYWORDS
+    }
+
+    fn builtins(&self) -> &'static [&'static str] {
+        PYTHON_BUILTINS
+    }
+}
+
+/// Top-level module names of `import a.b` / `from a.b import c`; relative imports are skipped.
+fn collect_imports(node: Node, code: &str, modules: &mut Vec<String>) {
+    let top_level = |name: Node| {
+        name.utf8_text(code.as_bytes())
+            .ok()
+            .and_then(|text| text.split('.').next())
+            .map(|module| module.trim().to_string())
+    };
+    match node.kind() {
+        "import_statement" => {
+            let mut cursor = node.walk();
+            for child in node.named_children(&mut cursor) {
+                let name = match child.kind() {
+                    "aliased_import" => child.child_by_field_name("name"),
+                    "dotted_name" => Some(child),
+                    _ => None,
+                };
+                modules.extend(name.and_then(top_level));
+            }
+        }
+        "import_from_statement" => {
+            let module_name = node
+                .child_by_field_name("module_name")
+                .filter(|module_name| module_name.kind() == "dotted_name");
+            modules.extend(module_name.and_then(top_level));
+        }
+        _ => {
+            let mut cursor = node.walk();
+            for child in node.named_children(&mut cursor) {
+                collect_imports(child, code, modules);
+            }
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn imports(code: &str) -> Vec<String> {
+        PythonValidator.imports(code).unwrap()
+    }
+
+    #[test]
+    fn imports_are_top_level_module_names() {
+        assert_eq!(
+            imports("import os, numpy.linalg as la\nimport collections.abc"),
+            ["os", "numpy", "collections"]
+        );
+        assert_eq!(
+            imports("from sklearn.linear_model import LinearRegression\nfrom bs4 import *"),
+            ["sklearn", "bs4"]
+        );
+    }
+
+    #[test]
+    fn nested_imports_are_found_and_relative_ones_skipped() {
+        let code = "\
+from . import sibling
+from .pkg import thing
+
+def solve():
+    import heapq
+    try:
+        import numpy as np
+    except ImportError:
+        pass
 
-        Ok(tree)
+class Solution:
+    def run(self):
+        from pandas import DataFrame
+";
+        assert_eq!(imports(code), ["heapq", "numpy", "pandas"]);
     }
 }
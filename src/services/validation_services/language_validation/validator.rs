@@ -1,8 +1,314 @@
-pub use crate::models::validation_models::ValidationError;
-use tree_sitter::Tree;
+pub use crate::models::validation_models::{CodeDiagnostic, EntryPoint, ValidationError};
+use crate::models::websocket_message_model::Position;
+use std::cell::RefCell;
+use std::collections::{HashMap, hash_map::Entry};
+use tree_sitter::{Language, LanguageError, Node, Parser, Query, Tree};
+
+// Longest snippet of unexpected code quoted in a diagnostic message
+const SNIPPET_CHARS: usize = 20;
+
+thread_local! {
+    // Parsers are kept per thread and grammar: creating one for every parse is slow, and a
+    // parser can only be used by one thread at a time
+    static PARSERS: RefCell<HashMap<Language, Parser>> = RefCell::new(HashMap::new());
+}
+
 pub trait SyntaxValidator {
-    fn validate(&self, code: &str) -> Result<Tree, ValidationError>;
+    // Parses without rejecting syntax errors; `validate` is the strict variant.
+    fn parse(&self, code: &str) -> Result<Tree, ValidationError> {
+        self.reparse(code, None)
+    }
+    // Parses `code` reusing `old_tree`, an earlier parse edited to match `code` (`Tree::edit`),
+    // so that only the edited parts are parsed again.
+    fn reparse(&self, code: &str, old_tree: Option<&Tree>) -> Result<Tree, ValidationError>;
+    // Parses `code`, failing with every syntax error found if there are any.
+    fn validate(&self, code: &str) -> Result<Tree, ValidationError> {
+        let tree = self.parse(code)?;
+        let diagnostics = syntax_diagnostics(&tree, code);
+        if !diagnostics.is_empty() {
+            let error_message = format!(
+                "Syntax error in code:\n{}",
+                describe_diagnostics(&diagnostics)
+            );
+            return Err(self.validation_error(&error_message));
+        }
+        Ok(tree)
+    }
     fn validation_error(&self, error: &str) -> ValidationError {
         ValidationError::InvalidCode(error.to_string())
     }
+    // Only languages that derive the file name / main symbol from the code override this.
+    fn entry_point(&self, _code: &str) -> Result<Option<EntryPoint>, ValidationError> {
+        Ok(None)
+    }
+    // Modules/packages the code imports, checked against the language's `packages` allow-list.
+    fn imports(&self, code: &str) -> Result<Vec<String>, ValidationError> {
+        let tree = self.parse(code)?;
+        Ok(self.tree_imports(&tree, code))
+    }
+    // `imports` of code already parsed into `tree`.
+    fn tree_imports(&self, _tree: &Tree, _code: &str) -> Vec<String> {
+        Vec::new()
+    }
+    fn is_standard_library(&self, _module: &str) -> bool {
+        false
+    }
+    // Finds the definitions shown in a document outline, see `outline::outline`.
+    fn outline_query(&self) -> Option<&'static Query> {
+        None
+    }
+    // Offered by completion along with the identifiers in the code, see `keywords`.
+    fn keywords(&self) -> &'static [&'static str] {
+        &[]
+    }
+    fn builtins(&self) -> &'static [&'static str] {
+        &[]
+    }
+}
+
+/// Parses `code` with this thread's parser for `language`, reusing `old_tree` if given. `None`
+/// if the parser gave up, which it only does when given a timeout or cancellation flag.
+pub fn parse_with(
+    language: Language,
+    code: &str,
+    old_tree: Option<&Tree>,
+) -> Result<Option<Tree>, LanguageError> {
+    PARSERS.with(|parsers| {
+        let mut parsers = parsers.borrow_mut();
+        let parser = match parsers.entry(language) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let mut parser = Parser::new();
+                parser.set_language(language)?;
+                entry.insert(parser)
+            }
+        };
+        Ok(parser.parse(code, old_tree))
+    })
+}
+
+/// The tree's `ERROR` and `MISSING` nodes as diagnostics, in order. `ERROR` nodes nest; only
+/// the innermost ones are reported, as they point closest to the mistake.
+pub fn syntax_diagnostics(tree: &Tree, code: &str) -> Vec<CodeDiagnostic> {
+    let mut diagnostics = Vec::new();
+    collect_syntax_errors(tree.root_node(), code, &mut diagnostics);
+    diagnostics
+}
+
+/// One line per diagnostic, e.g. "line 3, column 8: missing `)`", for `validate` errors.
+pub fn describe_diagnostics(diagnostics: &[CodeDiagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(|diagnostic| match diagnostic.range {
+            Some((start, _)) => format!(
+                "line {}, column {}: {}",
+                start.line + 1,
+                start.ch + 1,
+                diagnostic.message
+            ),
+            None => diagnostic.message.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn collect_syntax_errors(node: Node, code: &str, diagnostics: &mut Vec<CodeDiagnostic>) {
+    if !node.has_error() {
+        return;
+    }
+    let mut cursor = node.walk();
+    let nested_errors = node.children(&mut cursor).any(|child| child.has_error());
+    let message = if node.is_missing() {
+        Some(if node.is_named() {
+            format!("missing {}", node.kind().replace('_', " "))
+        } else {
+            format!("missing `{}`", node.kind())
+        })
+    } else if node.is_error() && !nested_errors {
+        Some(unexpected(node, code))
+    } else {
+        None
+    };
+    if let Some(message) = message {
+        diagnostics.push(CodeDiagnostic {
+            message,
+            range: Some((
+                position_at(code, node.start_byte()),
+                position_at(code, node.end_byte()),
+            )),
+        });
+        return;
+    }
+    for child in node.children(&mut cursor) {
+        collect_syntax_errors(child, code, diagnostics);
+    }
+}
+
+// Message for an `ERROR` node: the token the parser couldn't place or, when the node covers
+// a construct it couldn't finish, the start of that construct
+fn unexpected(node: Node, code: &str) -> String {
+    let text = node.utf8_text(code.as_bytes()).unwrap_or("").trim();
+    let first_line = text.lines().next().unwrap_or("");
+    if first_line.is_empty() {
+        return "unexpected end of input".to_string();
+    }
+    let mut snippet: String = first_line.chars().take(SNIPPET_CHARS).collect();
+    if snippet.len() < text.len() {
+        snippet.push_str("...");
+    }
+    if node.child_count() <= 1 {
+        format!("unexpected token `{}`", snippet)
+    } else {
+        format!("syntax error in `{}`", snippet)
+    }
+}
+
+// Tree-sitter counts columns in bytes; editors, and `Position`, count UTF-16 code units
+pub fn position_at(code: &str, byte: usize) -> Position {
+    let mut byte = byte.min(code.len());
+    while !code.is_char_boundary(byte) {
+        byte -= 1;
+    }
+    let before = &code[..byte];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    Position {
+        line: before.matches('\n').count(),
+        ch: before[line_start..].encode_utf16().count(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        java_validator::JavaValidator, javascipt_validator::JavaScriptValidator,
+        python_validator::PythonValidator,
+    };
+    use super::*;
+
+    fn position(line: usize, ch: usize) -> Position {
+        Position { line, ch }
+    }
+
+    fn diagnostics(
+        validator: &dyn SyntaxValidator,
+        code: &str,
+    ) -> Vec<(String, Position, Position)> {
+        let tree = validator.parse(code).unwrap();
+        syntax_diagnostics(&tree, code)
+            .into_iter()
+            .map(|diagnostic| {
+                let (start, end) = diagnostic.range.unwrap();
+                (diagnostic.message, start, end)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn missing_nodes_are_reported_where_they_belong() {
+        // An anonymous node by its text, a named one by its kind
+        assert_eq!(
+            diagnostics(&JavaScriptValidator, "let x = (1 + 2;\n"),
+            [("missing `)`".to_string(), position(0, 14), position(0, 14))]
+        );
+        assert_eq!(
+            diagnostics(&JavaValidator, "class A { int x = 1 }\n"),
+            [("missing `;`".to_string(), position(0, 19), position(0, 19))]
+        );
+        assert_eq!(
+            diagnostics(&JavaValidator, "class A { void f() { int = 2; } }\n"),
+            [(
+                "missing identifier".to_string(),
+                position(0, 24),
+                position(0, 24)
+            )]
+        );
+    }
+
+    #[test]
+    fn error_nodes_quote_the_unexpected_code() {
+        assert_eq!(
+            diagnostics(&PythonValidator, "x = = 1\n"),
+            [(
+                "unexpected token `=`".to_string(),
+                position(0, 4),
+                position(0, 5)
+            )]
+        );
+        // An unfinished construct is quoted from its start
+        assert_eq!(
+            diagnostics(&PythonValidator, "print(1"),
+            [(
+                "syntax error in `print(1`".to_string(),
+                position(0, 0),
+                position(0, 7)
+            )]
+        );
+    }
+
+    #[test]
+    fn columns_count_utf16_units_after_non_ascii_text() {
+        // "😀" is 4 bytes and 2 UTF-16 units, "é" 2 bytes and 1 unit
+        let line = "const s = '😀é'; let x = (1 + 2;";
+        let column = line.encode_utf16().count() - 1;
+        assert_eq!(
+            diagnostics(&JavaScriptValidator, &format!("// ü\n{}\n", line)),
+            [(
+                "missing `)`".to_string(),
+                position(1, column),
+                position(1, column)
+            )]
+        );
+        assert_eq!(
+            diagnostics(&PythonValidator, "s = \"😀é\"; t = = 1\n"),
+            [(
+                "unexpected token `=`".to_string(),
+                position(0, 15),
+                position(0, 16)
+            )]
+        );
+    }
+
+    #[test]
+    fn position_at_converts_bytes_to_utf16_positions() {
+        let code = "a😀b\nxé\n";
+        assert_eq!(position_at(code, 0), position(0, 0));
+        assert_eq!(position_at(code, 1), position(0, 1));
+        assert_eq!(position_at(code, 5), position(0, 3));
+        assert_eq!(position_at(code, 7), position(1, 0));
+        assert_eq!(position_at(code, 8), position(1, 1));
+        assert_eq!(position_at(code, 10), position(1, 2));
+        assert_eq!(position_at(code, 11), position(2, 0));
+        // Inside a character it rounds down to the character's start; past the end, clamps
+        assert_eq!(position_at(code, 3), position(0, 1));
+        assert_eq!(position_at(code, 9), position(1, 1));
+        assert_eq!(position_at(code, 100), position(2, 0));
+    }
+
+    #[test]
+    fn validate_fails_with_every_syntax_error() {
+        for validator in [
+            &PythonValidator as &dyn SyntaxValidator,
+            &JavaScriptValidator,
+            &JavaValidator,
+        ] {
+            assert!(validator.validate("").is_ok());
+        }
+        assert!(PythonValidator.validate("print('é')\n").is_ok());
+        match JavaScriptValidator.validate("let x = (1;\nlet = = 2;\n") {
+            Err(ValidationError::InvalidCode(message)) => {
+                assert!(
+                    message.starts_with("Syntax error in code:\n"),
+                    "{}",
+                    message
+                );
+                assert!(
+                    message.contains("line 1, column 11: missing `)`"),
+                    "{}",
+                    message
+                );
+                assert!(message.contains("line 2, "), "{}", message);
+            }
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+    }
 }
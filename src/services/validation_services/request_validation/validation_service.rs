@@ -1,16 +1,32 @@
-pub use crate::models::validation_models::{ValidRequest, ValidationError, ValidationService};
+pub use crate::models::validation_models::{
+    CodeDiagnostic, OutlineSymbol, SymbolKind, ValidRequest, ValidationError, ValidationService,
+};
 use crate::{
-    models::{docker_models::DockerSupportedLanguage, session_management_models::SessionError},
-    proto::executor::ExecuteRequest,
+    models::{session_management_models::SessionError, websocket_message_model::Position},
+    proto::executor::{
+        Diagnostic, ExecuteRequest, OutlineRequest, OutlineResponse, Symbol, TextPosition,
+        ValidateRequest, ValidateResponse,
+    },
     services::{
         all_session_services::session_management_service::SessionManagement,
-        helper_services::config_service::get_global_config,
+        helper_services::{
+            config_service::get_global_config,
+            language_registry_service::{LanguageDefinition, get_language_registry},
+            metrics_service::get_metrics,
+        },
+        validation_services::language_validation::{
+            completion::{Completion, complete},
+            outline::outline,
+            validator::{SyntaxValidator, syntax_diagnostics},
+        },
     },
 };
 
 use std::error::Error;
 use std::fmt;
+use std::time::Instant;
 use tonic::Request;
+use tree_sitter::Tree;
 
 impl ValidRequest {
     pub fn new(id: String, code: String, language: String) -> Self {
@@ -35,25 +51,26 @@ impl ValidRequest {
 
 impl fmt::Display for ValidationError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Validation Error : {:?}", self)
-    }
-}
-
-impl Error for ValidationError {}
-impl ValidationError {
-    fn to_string(&self) -> String {
         match self {
-            ValidationError::InvalidLanguage(lang) => {
+            ValidationError::InvalidLanguage(lang) => write!(
+                f,
+                "{}",
                 SessionError::message(&SessionError::InvalidLanguage(lang.clone()))
+            ),
+            ValidationError::InvalidCode(code) => write!(f, "Invalid Code {}", code),
+            ValidationError::EmptyCode() => write!(f, "Code must be provided:"),
+            ValidationError::EmptyLanguage() => write!(f, "Language must be specified"),
+            ValidationError::SessionIdError(msg) => write!(f, "Session ID error: {}", msg),
+            ValidationError::MissingEntryPoint(msg) => {
+                write!(f, "No runnable entry point: {}", msg)
             }
-            ValidationError::InvalidCode(code) => format!("Invalid Code {}", code),
-            ValidationError::EmptyCode() => format!("Code must be provided:"),
-            ValidationError::EmptyLanguage() => format!("Language must be specified"),
-            ValidationError::SessionIdError(msg) => format!("Session ID error: {}", msg),
+            ValidationError::DisallowedPackage(msg) => write!(f, "Package not allowed: {}", msg),
         }
     }
 }
 
+impl Error for ValidationError {}
+
 impl ValidationService {
     pub async fn validate_request(
         request: &Request<ExecuteRequest>,
@@ -71,7 +88,7 @@ impl ValidationService {
             }
             Err(e) => {
                 let error = ValidationError::SessionIdError(format!("{:?}", e));
-                println!("error {}", error.to_string());
+                println!("error {}", error);
                 return Err(error);
             }
         };
@@ -84,22 +101,209 @@ impl ValidationService {
         if language.is_empty() {
             return Err(ValidationError::EmptyLanguage());
         }
-        let docker_language = DockerSupportedLanguage::is_supported(&language);
+        let language =
+            match get_language_registry().resolve_version(&language, Some(&request_data.version)) {
+                Some(definition) => {
+                    println!("Language is valid: {}", definition.key());
+                    definition
+                }
+                None => return Err(ValidationError::InvalidLanguage(format!("{:?}", language))),
+            };
+
+        // Empty code runs the session's current buffer, which `session_handler` checks
+        if !code.is_empty() {
+            Self::validate_code(&language, &code)?;
+        }
+
+        return Ok(ValidRequest::new(session_id, code, language.key()));
+    }
+
+    /// Checks that `code` can be run as `language`: it has an entry point and only
+    /// imports allowed packages.
+    pub fn validate_code(language: &LanguageDefinition, code: &str) -> Result<(), ValidationError> {
+        // Languages like Java need a runnable class before we can name and run the file
+        if let Err(e) = language.entry_point(code) {
+            eprintln!("Entry point error: {}", e);
+            return Err(e);
+        }
+        if let Err(e) = language.check_imports(code) {
+            eprintln!("Import error: {}", e);
+            return Err(e);
+        }
+        Ok(())
+    }
 
-        if docker_language.is_none() {
-            return Err(ValidationError::InvalidLanguage(format!("{:?}", language)));
+    /// Problems in `code` for an editor: every syntax error with its range or, when the syntax
+    /// is fine, a disallowed import. Languages without a grammar aren't checked. `old_tree` is
+    /// the previous parse, edited to match `code`, so only the edited parts are parsed again;
+    /// the new parse is returned for next time.
+    pub fn diagnose(
+        language: &LanguageDefinition,
+        code: &str,
+        old_tree: Option<&Tree>,
+    ) -> (Vec<CodeDiagnostic>, Option<Tree>) {
+        let validator = match language.validator() {
+            Some(validator) => validator,
+            None => {
+                println!(
+                    "No grammar configured for {}, skipping syntax check",
+                    language.name
+                );
+                return (Vec::new(), None);
+            }
+        };
+        let tree = match Self::reparse(validator.as_ref(), code, old_tree) {
+            Ok(tree) => tree,
+            Err(e) => return (vec![CodeDiagnostic::unplaced(&e)], None),
+        };
+        let mut diagnostics = syntax_diagnostics(&tree, code);
+        if diagnostics.is_empty()
+            && let Err(e) = language.check_tree_imports(&tree, code)
+        {
+            diagnostics.push(CodeDiagnostic::unplaced(&e));
+        }
+        if diagnostics.is_empty() {
+            println!("✅ {} syntax is valid", language.name);
         } else {
-            println!(
-                "Language is valid: {}",
-                DockerSupportedLanguage::to_string(&docker_language.unwrap())
+            eprintln!(
+                "❌ {} problem(s) in {} code",
+                diagnostics.len(),
+                language.name
             );
         }
+        (diagnostics, Some(tree))
+    }
+
+    /// The classes, functions and methods defined in `code`, for an editor's symbol sidebar.
+    /// Code with syntax errors still gets the definitions the parser recognised. Empty for
+    /// languages without a grammar. `old_tree` and the returned tree are as in `diagnose`.
+    pub fn outline(
+        language: &LanguageDefinition,
+        code: &str,
+        old_tree: Option<&Tree>,
+    ) -> Result<(Vec<OutlineSymbol>, Option<Tree>), ValidationError> {
+        let Some(validator) = language.validator() else {
+            return Ok((Vec::new(), None));
+        };
+        let tree = Self::reparse(validator.as_ref(), code, old_tree)?;
+        let symbols = validator
+            .outline_query()
+            .map(|query| outline(query, &tree, code))
+            .unwrap_or_default();
+        Ok((symbols, Some(tree)))
+    }
 
-        if code.is_empty() {
-            eprint!("{:?}", ValidationError::EmptyCode());
-            return Err(ValidationError::EmptyCode());
+    /// Completions for the word ending at byte `offset` of `code`, and the byte offset where that
+    /// word starts; see `completion::complete`. None for languages without a grammar.
+    /// `old_tree` and the returned tree are as in `diagnose`.
+    pub fn complete(
+        language: &LanguageDefinition,
+        code: &str,
+        old_tree: Option<&Tree>,
+        offset: usize,
+    ) -> Result<(usize, Vec<Completion>, Option<Tree>), ValidationError> {
+        let Some(validator) = language.validator() else {
+            return Ok((offset, Vec::new(), None));
+        };
+        let tree = Self::reparse(validator.as_ref(), code, old_tree)?;
+        let (start, completions) = complete(validator.as_ref(), &tree, code, offset);
+        Ok((start, completions, Some(tree)))
+    }
+
+    /// The `GetOutline` RPC: the outline of code in the requested language.
+    pub fn get_outline(request: OutlineRequest) -> Result<OutlineResponse, ValidationError> {
+        let language = get_language_registry()
+            .resolve_version(&request.language.to_lowercase(), Some(&request.version))
+            .ok_or_else(|| ValidationError::InvalidLanguage(request.language.clone()))?;
+        let (symbols, _) = Self::outline(&language, &request.code, None)?;
+        Ok(OutlineResponse {
+            symbols: symbols.into_iter().map(Symbol::from).collect(),
+        })
+    }
+
+    // Parses with `validator`, timing full parses and incremental ones separately
+    fn reparse(
+        validator: &dyn SyntaxValidator,
+        code: &str,
+        old_tree: Option<&Tree>,
+    ) -> Result<Tree, ValidationError> {
+        let started = Instant::now();
+        let tree = validator.reparse(code, old_tree)?;
+        get_metrics().observe(
+            if old_tree.is_some() {
+                "buffer_reparse"
+            } else {
+                "buffer_parse"
+            },
+            started.elapsed(),
+        );
+        Ok(tree)
+    }
+
+    /// The `Validate` RPC: diagnostics for code in the requested language, without running it.
+    pub fn validate(request: ValidateRequest) -> Result<ValidateResponse, ValidationError> {
+        let language = get_language_registry()
+            .resolve_version(&request.language.to_lowercase(), Some(&request.version))
+            .ok_or_else(|| ValidationError::InvalidLanguage(request.language.clone()))?;
+        let (diagnostics, _) = Self::diagnose(&language, &request.code, None);
+        let diagnostics: Vec<Diagnostic> = diagnostics.into_iter().map(Diagnostic::from).collect();
+        Ok(ValidateResponse {
+            valid: diagnostics.is_empty(),
+            diagnostics,
+        })
+    }
+}
+
+impl CodeDiagnostic {
+    pub fn unplaced(error: &ValidationError) -> Self {
+        CodeDiagnostic {
+            message: error.to_string(),
+            range: None,
         }
+    }
+}
 
-        return Ok(ValidRequest::new(session_id, code, language));
+impl From<CodeDiagnostic> for Diagnostic {
+    fn from(diagnostic: CodeDiagnostic) -> Self {
+        Diagnostic {
+            severity: "error".to_string(),
+            message: diagnostic.message,
+            start: diagnostic.range.map(|(start, _)| start.into()),
+            end: diagnostic.range.map(|(_, end)| end.into()),
+        }
+    }
+}
+
+impl From<OutlineSymbol> for Symbol {
+    fn from(symbol: OutlineSymbol) -> Self {
+        Symbol {
+            kind: symbol.kind.as_str().to_string(),
+            name: symbol.name,
+            start: Some(symbol.range.0.into()),
+            end: Some(symbol.range.1.into()),
+            children: symbol.children.into_iter().map(Symbol::from).collect(),
+        }
+    }
+}
+
+impl From<Position> for TextPosition {
+    fn from(position: Position) -> Self {
+        TextPosition {
+            line: position.line as u32,
+            column: position.ch as u32,
+        }
+    }
+}
+
+impl SymbolKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SymbolKind::Class => "class",
+            SymbolKind::Interface => "interface",
+            SymbolKind::Enum => "enum",
+            SymbolKind::Function => "function",
+            SymbolKind::Method => "method",
+            SymbolKind::Constructor => "constructor",
+        }
     }
 }
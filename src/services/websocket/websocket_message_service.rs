@@ -1,10 +1,18 @@
-use std::str::FromStr;
-
-use crate::models::validation_models::ValidationError;
+use crate::models::{
+    executor_models::{ExecutionResult, OutputChunk},
+    in_memory_session_cache_model::SessionError,
+    session_management_models::SessionKey,
+    validation_models::{CodeDiagnostic, OutlineSymbol, ValidationError},
+};
 pub use crate::{
-    models::websocket_message_model::{Code, CodeType, Patch, Position, WebSocketMessage},
+    models::websocket_message_model::{
+        ClientMessage, Code, CodeType, CompleteMessage, Diagnostic, DiagnosticSeverity,
+        JoinMessage, PROTOCOL_VERSION, ParticipantRole, Patch, Position, PresenceMessage,
+        ResponseBody, RunMessage, RunStatus, Selection, SessionTarget, Symbol, WebSocketMessage,
+        WebSocketResponse,
+    },
     services::{
-        helper_services::docker_service::DockerSupportedLanguage as DockerSupportedLanguageService,
+        helper_services::language_registry_service::{LanguageDefinition, get_language_registry},
         validation_services::request_validation::validation_service::ValidationError::InvalidLanguage,
     },
 };
@@ -16,40 +24,51 @@ impl WebSocketMessage {
     //     parsed
     // }
 
-    pub fn generate_session_id(&self) -> Result<String, ValidationError> {
-        match self.get_language() {
-            Ok(language) => {
-                // Convert the language to a string representation
-                let language_str = DockerSupportedLanguageService::to_string(&language);
-                // Generate a session ID based on the language and content
-                Ok(format!("{}-{}", language_str, self.session_id))
-            }
-            Err(e) => Err(e),
-        }
+    /// The session this message edits; the same key gRPC uses for `session_id` + language.
+    pub fn session_key(&self) -> Result<SessionKey, ValidationError> {
+        let language = self.get_language()?;
+        Ok(SessionKey::new(self.session_id.clone(), language.key()))
     }
 
-    pub fn get_language(&self) -> Result<DockerSupportedLanguageService, ValidationError> {
-        if DockerSupportedLanguageService::is_supported(&self.language).is_none() {
-            return Err(InvalidLanguage((&self.language).clone()));
-        }
-        // Convert the language string to DockerSupportedLanguage enum
-        Ok(DockerSupportedLanguageService::from_str(&self.language)
-            .expect("Failed to parse DockerSupportedLanguage from language string"))
-    }
-
-    pub fn get_code_string(&self) -> String {
-        // Convert the code to a string representation
-        match &self.code {
-            Code::Full { content } => content.clone(),
-            Code::Patch { patches } => patches
-                .iter()
-                .map(|p| p.text.clone())
-                .collect::<Vec<String>>()
-                .join("\n"),
+    pub fn get_language(&self) -> Result<LanguageDefinition, ValidationError> {
+        resolve_language(&self.language, self.version.as_deref())
+    }
+}
+
+impl SessionTarget {
+    pub fn session_key(&self) -> Result<SessionKey, ValidationError> {
+        let language = self.get_language()?;
+        Ok(SessionKey::new(self.session_id.clone(), language.key()))
+    }
+
+    pub fn get_language(&self) -> Result<LanguageDefinition, ValidationError> {
+        resolve_language(&self.language, self.version.as_deref())
+    }
+}
+
+impl ClientMessage {
+    /// Parses a client message; one without a `type` is an edit.
+    pub fn parse(text: &str) -> Result<Self, serde_json::Error> {
+        let mut value: serde_json::Value = serde_json::from_str(text)?;
+        if let Some(object) = value.as_object_mut() {
+            object
+                .entry("type")
+                .or_insert_with(|| serde_json::Value::from("edit"));
         }
+        serde_json::from_value(value)
     }
 }
 
+// Resolves the language name or alias against the configured registry
+fn resolve_language(
+    language: &str,
+    version: Option<&str>,
+) -> Result<LanguageDefinition, ValidationError> {
+    get_language_registry()
+        .resolve_version(language, version)
+        .ok_or_else(|| InvalidLanguage(language.to_string()))
+}
+
 impl CodeType {
     fn from_str(s: &str) -> Self {
         match s.to_lowercase().as_str() {
@@ -67,6 +86,7 @@ impl Code {
                 content: String::new(),
             }, // Placeholder for full code
             "patch" => Code::Patch {
+                base_revision: 0,
                 patches: Vec::new(),
             }, // Placeholder for patches
             _ => panic!("Unknown code type: {}", s),
@@ -85,3 +105,246 @@ impl Patch {
         Patch { start, end, text }
     }
 }
+
+impl WebSocketResponse {
+    pub fn new(body: ResponseBody) -> Self {
+        WebSocketResponse {
+            version: PROTOCOL_VERSION,
+            body,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|e| {
+            // Only reachable if a field stops being serializable; still answer with valid JSON
+            eprintln!("Cannot serialize WebSocket response: {}", e);
+            format!(
+                r#"{{"version":{},"type":"error","error_type":"internal_error","code":500,"message":"Cannot serialize response"}}"#,
+                PROTOCOL_VERSION
+            )
+        })
+    }
+}
+
+impl From<SessionError> for ResponseBody {
+    fn from(error: SessionError) -> Self {
+        ResponseBody::Error {
+            session_id: None,
+            language: None,
+            error_type: error.error_type,
+            code: error.error_code,
+            message: error.message,
+        }
+    }
+}
+
+impl ResponseBody {
+    /// Names the session of the message an error answers; other responses already do.
+    pub fn about(mut self, target_session_id: &str, target_language: &str) -> Self {
+        if let ResponseBody::Error {
+            session_id,
+            language,
+            ..
+        } = &mut self
+        {
+            *session_id = Some(target_session_id.to_string());
+            *language = Some(target_language.to_string());
+        }
+        self
+    }
+
+    /// Final response of a run that finished, successfully or not, inside its container.
+    pub fn run_completed(key: &SessionKey, result: ExecutionResult) -> Self {
+        ResponseBody::Execution {
+            session_id: key.session_id.clone(),
+            language: key.language.clone(),
+            status: RunStatus::Completed,
+            output: result.output,
+            runtime_version: result.runtime_version,
+            session_ttl_seconds: result.session_ttl_secs,
+            concurrency: result.disposition.as_str().to_string(),
+            environment_lost: result.environment_lost,
+            message: None,
+        }
+    }
+
+    /// Final response of a run that was cancelled or couldn't run (or finish).
+    pub fn run_failed(key: &SessionKey, status: RunStatus, message: String) -> Self {
+        ResponseBody::Execution {
+            session_id: key.session_id.clone(),
+            language: key.language.clone(),
+            status,
+            output: String::new(),
+            runtime_version: String::new(),
+            session_ttl_seconds: 0,
+            concurrency: String::new(),
+            environment_lost: None,
+            message: Some(message),
+        }
+    }
+
+    pub fn output(key: &SessionKey, chunk: OutputChunk) -> Self {
+        ResponseBody::Output {
+            session_id: key.session_id.clone(),
+            language: key.language.clone(),
+            stream: chunk.stream,
+            data: chunk.data,
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn error(error: &ValidationError) -> Self {
+        Diagnostic {
+            severity: DiagnosticSeverity::Error,
+            message: error.to_string(),
+            start: None,
+            end: None,
+        }
+    }
+}
+
+impl From<CodeDiagnostic> for Diagnostic {
+    fn from(diagnostic: CodeDiagnostic) -> Self {
+        Diagnostic {
+            severity: DiagnosticSeverity::Error,
+            message: diagnostic.message,
+            start: diagnostic.range.map(|(start, _)| start),
+            end: diagnostic.range.map(|(_, end)| end),
+        }
+    }
+}
+
+impl From<OutlineSymbol> for Symbol {
+    fn from(symbol: OutlineSymbol) -> Self {
+        Symbol {
+            kind: symbol.kind,
+            name: symbol.name,
+            start: symbol.range.0,
+            end: symbol.range.1,
+            children: symbol.children.into_iter().map(Symbol::from).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::in_memory_session_cache_model::SessionErrorType;
+    use serde_json::{Value, json};
+
+    // Serializes, parses back and serializes again; both must be `expected`
+    fn assert_response_round_trips(body: ResponseBody, expected: Value) {
+        let json = WebSocketResponse::new(body).to_json();
+        assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), expected);
+        let parsed: WebSocketResponse = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), expected);
+    }
+
+    #[test]
+    fn the_protocol_version_is_pinned() {
+        // Clients check it; bump it only with a breaking change to the messages
+        assert_eq!(PROTOCOL_VERSION, 1);
+    }
+
+    #[test]
+    fn responses_round_trip_with_their_type_tags() {
+        let key = SessionKey::new("abc".to_string(), "python@3.12".to_string());
+        assert_response_round_trips(
+            ResponseBody::Ack {
+                session_id: "abc".to_string(),
+                language: "python@3.12".to_string(),
+                revision: 7,
+            },
+            json!({"version": 1, "type": "ack", "session_id": "abc",
+                   "language": "python@3.12", "revision": 7}),
+        );
+        assert_response_round_trips(
+            ResponseBody::Change {
+                session_id: "abc".to_string(),
+                language: "python@3.12".to_string(),
+                revision: 8,
+                code: Code::Full {
+                    content: "x = 1".to_string(),
+                },
+            },
+            json!({"version": 1, "type": "change", "session_id": "abc",
+                   "language": "python@3.12", "revision": 8, "code_type": "full",
+                   "content": "x = 1"}),
+        );
+        assert_response_round_trips(
+            ResponseBody::ParticipantLeft {
+                session_id: "abc".to_string(),
+                language: "python@3.12".to_string(),
+                participant: 3,
+            },
+            json!({"version": 1, "type": "participant_left", "session_id": "abc",
+                   "language": "python@3.12", "participant": 3}),
+        );
+        assert_response_round_trips(
+            ResponseBody::run_failed(&key, RunStatus::Cancelled, "cancelled".to_string()),
+            json!({"version": 1, "type": "execution", "session_id": "abc",
+                   "language": "python@3.12", "status": "cancelled", "output": "",
+                   "runtime_version": "", "session_ttl_seconds": 0, "concurrency": "",
+                   "message": "cancelled"}),
+        );
+    }
+
+    #[test]
+    fn errors_name_the_session_of_the_rejected_message() {
+        let error = || SessionError::new("Stale".to_string(), SessionErrorType::Conflict, 409);
+        assert_response_round_trips(
+            ResponseBody::from(error()).about("abc", "python"),
+            json!({"version": 1, "type": "error", "session_id": "abc", "language": "python",
+                   "error_type": "conflict", "code": 409, "message": "Stale"}),
+        );
+        // An unparsable message names no session
+        assert_response_round_trips(
+            ResponseBody::from(error()),
+            json!({"version": 1, "type": "error", "error_type": "conflict", "code": 409,
+                   "message": "Stale"}),
+        );
+    }
+
+    #[test]
+    fn client_messages_round_trip_with_their_type_tags() {
+        let messages = [
+            json!({"type": "edit", "session_id": "abc", "language": "python", "version": null,
+                   "code_type": "patch", "base_revision": 6,
+                   "patches": [{"start": {"line": 1, "ch": 0}, "end": {"line": 1, "ch": 5},
+                                "text": "y"}]}),
+            json!({"type": "run", "session_id": "abc", "language": "python", "version": "3.12",
+                   "stdin": "42\n", "limits": {"timeout_secs": 5, "memory_mb": 0}}),
+            json!({"type": "cancel", "session_id": "abc", "language": "python", "version": null}),
+            json!({"type": "resync", "session_id": "abc", "language": "python", "version": null}),
+            json!({"type": "join", "session_id": "abc", "language": "python", "version": null,
+                   "role": "observer", "name": "ada"}),
+            json!({"type": "leave", "session_id": "abc", "language": "python", "version": null}),
+            json!({"type": "presence", "session_id": "abc", "language": "python",
+                   "version": null, "revision": 7, "cursor": {"line": 2, "ch": 4},
+                   "selection": {"anchor": {"line": 2, "ch": 0}, "head": {"line": 2, "ch": 4}}}),
+            json!({"type": "outline", "session_id": "abc", "language": "python", "version": null}),
+            json!({"type": "complete", "session_id": "abc", "language": "python",
+                   "version": null, "revision": 7, "position": {"line": 0, "ch": 3}}),
+        ];
+        for message in messages {
+            let parsed = ClientMessage::parse(&message.to_string()).unwrap();
+            assert_eq!(serde_json::to_value(&parsed).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn a_client_message_without_a_type_is_an_edit() {
+        let parsed = ClientMessage::parse(
+            r#"{"session_id": "abc", "language": "python", "code_type": "full", "content": "x"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            parsed,
+            ClientMessage::Edit(WebSocketMessage {
+                code: Code::Full { .. },
+                ..
+            })
+        ));
+    }
+}
@@ -1,24 +1,45 @@
 use std::any::Any;
-use std::net::SocketAddr;
 
 use futures_util::{SinkExt, StreamExt};
 use tokio::net::TcpListener;
+use tokio::sync::mpsc::{self, UnboundedSender};
 use tokio_tungstenite::accept_async;
+use tree_sitter::Tree;
+use tungstenite::Message;
 
 use crate::{
-    models::{docker_models::DockerSupportedLanguage, websocket_message_model::WebSocketMessage},
+    models::{
+        executor_models::RunOptions,
+        in_memory_session_cache_model::{SessionError, SessionErrorType},
+        language_registry_models::LanguageDefinition,
+        session_management_models::{self, SessionKey},
+        validation_models::{ValidRequest, ValidationService},
+    },
+    proto::executor::SessionLimits,
     services::{
         all_session_services::{
-            session_cache_service::{Session, SessionCache},
-            session_service::update_create_session,
+            session_management_service::SessionManagement,
+            session_service::{open_session, update_create_session},
+        },
+        execution_services::{code_editor_service::CodeEditor, executor_service::session_handler},
+        helper_services::config_service::get_global_config,
+        validation_services::language_validation::validator::position_at,
+        websocket::websocket_message_service::{
+            ClientMessage, CompleteMessage, Diagnostic, JoinMessage, ParticipantRole,
+            PresenceMessage, ResponseBody, RunMessage, RunStatus, Selection, SessionTarget, Symbol,
+            WebSocketMessage, WebSocketResponse,
         },
-        helper_services::config_service::{get_global_config, get_global_config_mut},
-        validation_services::language_validation::get_validator,
-        websocket::websocket_sessionpool_service::ConnectionManager,
+        websocket::websocket_sessionpool_service::{ConnectionManager, Participant},
     },
     utils::helper_utils::sanitize_code_content,
 };
 
+// One client socket: its id among session participants and the sender feeding its writer
+struct Connection {
+    id: u64,
+    responses: UnboundedSender<Message>,
+}
+
 pub async fn run_websocket_server(
     addr: &str,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -33,89 +54,38 @@ pub async fn run_websocket_server(
 
         tokio::spawn(async move {
             match accept_async(stream).await {
-                Ok(mut websocket) => {
+                Ok(websocket) => {
                     println!("New WebSocket connection");
-                    let mut session_cache: Option<&'static SessionCache> = None; // initialize properly
-                    let mut session: Option<Session> = None; // store Session by value
-                    while let Some(msg) = websocket.next().await {
+                    let (mut sink, mut incoming) = websocket.split();
+                    // Runs answer from their own tasks, so every response goes through one writer
+                    let (responses, mut outgoing) = mpsc::unbounded_channel::<Message>();
+                    tokio::spawn(async move {
+                        while let Some(message) = outgoing.recv().await {
+                            let closing = matches!(message, Message::Close(_));
+                            if let Err(e) = sink.send(message).await {
+                                eprintln!("WebSocket send error: {}", e);
+                                break;
+                            }
+                            if closing {
+                                break;
+                            }
+                        }
+                    });
+                    let connection = Connection {
+                        id: ConnectionManager::get_connection_manager().next_connection_id(),
+                        responses,
+                    };
+                    while let Some(msg) = incoming.next().await {
                         match msg {
-                            Ok(tungstenite::Message::Text(input_text)) => {
+                            Ok(Message::Text(input_text)) => {
                                 println!("Received message: {}", input_text);
-                                let mut text = sanitize_code_content(&input_text);
+                                let text = sanitize_code_content(&input_text);
                                 println!("Sanitized message: {}", text);
-
-                                match serde_json::from_str::<WebSocketMessage>(&text) {
-                                    Ok(message) => {
-                                        let session_id = message.session_id.clone();
-                                        let new_session_cache = session_management_add().await;
-                                        session_cache = Some(new_session_cache);
-                                        // websocket_connection_pool_add(peer_addr, session_id).await;
-                                        println!("Parsed WebSocketMessage: {:?}", message);
-
-                                        match update_create_session(&message) {
-                                            Ok(ss) => {
-                                                let new_session = ss.clone();
-                                                session = Some(new_session.clone());
-                                                new_session_cache
-                                                    .insert_session(new_session.clone());
-
-                                                let session_validation_message =
-                                                    valid_session_process(
-                                                        &new_session,
-                                                        &new_session_cache,
-                                                    );
-                                                println!(
-                                                    "Session process validation: {}",
-                                                    session_validation_message
-                                                );
-
-                                                let is_syntex_valid = syntex_validation(
-                                                    message.get_language().unwrap(),
-                                                    message.get_code_string(),
-                                                );
-
-                                                text.push_str(&format!(
-                                                    " (Session ID: {}, Language: {}, Code: {}, Syntax Valid: {})",
-                                                    message.session_id,
-                                                    message.language,
-                                                    message.get_code_string(),
-                                                    is_syntex_valid
-                                                ));
-                                            }
-                                            Err(e) => {
-                                                text.push_str(&format!(
-                                                    " Error: {}, Code: {}, Type: {:?}",
-                                                    e.message, e.error_code, e.error_type
-                                                ));
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        eprintln!("Failed to parse WebSocketMessage: {}", e);
-
-                                        let error_response =
-                                            format!("Error parsing message: {}", e.to_string());
-                                        if let Err(e) = websocket
-                                            .send(tungstenite::Message::Text(error_response))
-                                            .await
-                                        {
-                                            eprintln!("WebSocket send error: {}", e);
-                                            break;
-                                        }
-                                    }
-                                }
-
-                                if let Err(e) =
-                                    websocket.send(tungstenite::Message::Text(text)).await
-                                {
-                                    eprintln!("WebSocket send error: {}", e);
-                                    break;
-                                }
+                                handle_message(&text, &connection).await;
                             }
-                            Ok(tungstenite::Message::Close(_)) => {
+                            Ok(Message::Close(_)) => {
                                 println!("WebSocket connection closed");
-                                close_connection(&mut websocket, session.as_ref(), session_cache)
-                                    .await;
+                                close_connection(&connection.responses);
                                 break;
                             }
                             Err(e) => {
@@ -125,6 +95,7 @@ pub async fn run_websocket_server(
                             _ => {}
                         }
                     }
+                    ConnectionManager::get_connection_manager().leave_all(connection.id);
                 }
                 Err(e) => {
                     eprintln!("Error during WebSocket handshake: {}", e);
@@ -135,85 +106,768 @@ pub async fn run_websocket_server(
 
     Ok(())
 }
-fn valid_session_process(session: &Session, session_cache: &'static SessionCache) -> String {
-    const TAG: &str = "DEBUG||";
-    let verify_session = session_cache.get_session(&(session).session_id.as_str());
-    match &verify_session {
-        Some(session_ref) => {
-            let v_session = session_ref.value();
-            // println!("Session verified from cache: {:?}", v_session);
-            if v_session.session_id != session.session_id {
-                return format!("{}Session ID mismatch after insertion", TAG);
-            } else {
-                return format!("{}Session ID matches after insertion", TAG);
+/// Handles one client message. Edits are answered right away; a run answers from its own
+/// task, see `start_run`.
+async fn handle_message(text: &str, connection: &Connection) {
+    let responses = &connection.responses;
+    let message = match ClientMessage::parse(text) {
+        Ok(message) => message,
+        Err(e) => {
+            eprintln!("Failed to parse WebSocket message: {}", e);
+            respond(
+                responses,
+                SessionError::new(
+                    format!("Error parsing message: {}", e),
+                    SessionErrorType::InvalidInput,
+                    400,
+                )
+                .into(),
+            );
+            return;
+        }
+    };
+    println!("Parsed WebSocket message: {:?}", message);
+    match message {
+        ClientMessage::Edit(message) => handle_edit(&message, connection).await,
+        ClientMessage::Run(message) => start_run(message, connection),
+        ClientMessage::Cancel(target) => {
+            if let Some(body) = cancel_run(&target, connection).await {
+                respond(responses, body.about(&target.session_id, &target.language));
+            }
+        }
+        ClientMessage::Resync(target) => {
+            let body = resync(&target).await;
+            respond(responses, body.about(&target.session_id, &target.language));
+        }
+        ClientMessage::Join(message) => join(&message, connection).await,
+        ClientMessage::Leave(target) => {
+            if let Some(body) = leave(&target, connection) {
+                respond(responses, body.about(&target.session_id, &target.language));
+            }
+        }
+        ClientMessage::Presence(message) => {
+            if let Some(body) = update_presence(&message, connection).await {
+                let target = &message.target;
+                respond(responses, body.about(&target.session_id, &target.language));
             }
         }
-        None => {
-            return format!("{}Session not found in cache", TAG);
+        ClientMessage::Outline(target) => {
+            let body = outline(&target).await;
+            respond(responses, body.about(&target.session_id, &target.language));
+        }
+        ClientMessage::Complete(message) => {
+            let body = complete(&message).await;
+            let target = &message.target;
+            respond(responses, body.about(&target.session_id, &target.language));
         }
     }
 }
 
-fn syntex_validation(language: DockerSupportedLanguage, code: String) -> bool {
-    let validator = get_validator(language.clone());
-    match validator.validate(&code) {
-        Ok(_) => {
-            println!("✅ {:?} syntax is valid", language);
-            true
+/// Adds the connection to the session's participants with the requested role, creating the
+/// (empty) session if needed, and answers with the whole buffer. Changes made by the other
+/// participants follow as `change` responses.
+async fn join(message: &JoinMessage, connection: &Connection) {
+    let target = &message.target;
+    let key = match target.session_key() {
+        Ok(key) => key,
+        Err(e) => {
+            respond(
+                &connection.responses,
+                ResponseBody::from(SessionError::new(
+                    e.to_string(),
+                    SessionErrorType::InvalidInput,
+                    400,
+                ))
+                .about(&target.session_id, &target.language),
+            );
+            return;
         }
+    };
+    let manager = ConnectionManager::get_connection_manager();
+    let room = manager.room(&key);
+    {
+        // Joining between two edits, so the buffer sent and the changes that follow line up
+        let _turn = room.sequencer.lock().await;
+        let body = match open_session(&key).await.map_err(SessionError::from) {
+            Ok(session) => {
+                let participants = manager.join(
+                    &key,
+                    connection.id,
+                    Participant {
+                        role: message.role,
+                        name: message.name.clone(),
+                        cursor: None,
+                        selection: None,
+                        responses: connection.responses.clone(),
+                    },
+                );
+                println!(
+                    "Connection {} joined session {} as {:?}",
+                    connection.id,
+                    key.to_string(),
+                    message.role
+                );
+                ResponseBody::Joined {
+                    session_id: key.session_id.clone(),
+                    language: key.language.clone(),
+                    participant: connection.id,
+                    role: message.role,
+                    revision: session.editor.revision,
+                    content: session.editor.to_string(),
+                    participants,
+                }
+            }
+            Err(e) => ResponseBody::from(e).about(&target.session_id, &target.language),
+        };
+        respond(&connection.responses, body);
+    }
+    drop(room);
+    manager.release_room(&key);
+}
+
+/// Stops sending the session's changes to the connection. Answers only if it hadn't joined.
+fn leave(target: &SessionTarget, connection: &Connection) -> Option<ResponseBody> {
+    let key = match target.session_key() {
+        Ok(key) => key,
         Err(e) => {
-            eprintln!("❌ Syntax error: {}", e);
-            false
+            return Some(
+                SessionError::new(e.to_string(), SessionErrorType::InvalidInput, 400).into(),
+            );
         }
+    };
+    if ConnectionManager::get_connection_manager().leave(&key, connection.id) {
+        return None;
     }
+    Some(
+        SessionError::new(
+            format!("Not a participant of session '{}'", key.to_string()),
+            SessionErrorType::NotFound,
+            404,
+        )
+        .into(),
+    )
 }
 
-async fn close_connection(
-    websocket: &mut tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>,
-    session: Option<&Session>,
-    session_cache: Option<&'static SessionCache>,
-) {
-    // Attempt to send a close frame to the client
-    if let Err(e) = websocket.send(tungstenite::Message::Close(None)).await {
-        eprintln!("Error sending WebSocket close frame: {}", e);
+/// Records the connection's cursor and selection, mapped to the buffer's current revision, and
+/// sends them to the session's other participants. Answers only on error.
+async fn update_presence(
+    message: &PresenceMessage,
+    connection: &Connection,
+) -> Option<ResponseBody> {
+    let key = match message.target.session_key() {
+        Ok(key) => key,
+        Err(e) => {
+            return Some(
+                SessionError::new(e.to_string(), SessionErrorType::InvalidInput, 400).into(),
+            );
+        }
+    };
+    let session_management_service = get_global_config(|config| config.clone())
+        .await
+        .session_management_service?;
+    let manager = ConnectionManager::get_connection_manager();
+    let room = manager.room(&key);
+    let result = {
+        // Between edits, so the positions are mapped to the revision the next change builds on
+        let _turn = room.sequencer.lock().await;
+        let mut positions: Vec<_> = message.cursor.into_iter().collect();
+        if let Some(selection) = message.selection {
+            positions.extend([selection.anchor, selection.head]);
+        }
+        match session_management_service
+            .map_positions(&key.session_id, &key.language, message.revision, &positions)
+            .await
+        {
+            Ok((revision, mapped)) => {
+                let mut mapped = mapped.into_iter();
+                let cursor = message.cursor.and_then(|_| mapped.next());
+                let selection = message.selection.and_then(|_| {
+                    Some(Selection {
+                        anchor: mapped.next()?,
+                        head: mapped.next()?,
+                    })
+                });
+                match manager.update_presence(&key, connection.id, cursor, selection) {
+                    Some(participant) => {
+                        let body = ResponseBody::Presence {
+                            session_id: key.session_id.clone(),
+                            language: key.language.clone(),
+                            revision,
+                            participant,
+                        };
+                        room.broadcast(connection.id, &WebSocketResponse::new(body).to_json());
+                        Ok(())
+                    }
+                    None => Err(SessionError::new(
+                        format!("Join session '{}' before sending presence", key.to_string()),
+                        SessionErrorType::NotFound,
+                        404,
+                    )),
+                }
+            }
+            Err(e) => Err(SessionError::from(e)),
+        }
+    };
+    drop(room);
+    manager.release_room(&key);
+    result.err().map(ResponseBody::from)
+}
+
+// Once a session has participants, only those who joined it as editors may edit, run or
+// cancel; a session nobody joined is open to any connection
+fn ensure_editor(key: &SessionKey, connection: &Connection) -> Result<(), SessionError> {
+    let manager = ConnectionManager::get_connection_manager();
+    let refused = match manager.role(key, connection.id) {
+        Some(ParticipantRole::Editor) => return Ok(()),
+        Some(ParticipantRole::Observer) => {
+            format!("Observers of session '{}' can't change it", key.to_string())
+        }
+        None if manager.has_participants(key) => format!(
+            "Join session '{}' as an editor before changing it",
+            key.to_string()
+        ),
+        None => return Ok(()),
+    };
+    Err(SessionError::new(refused, SessionErrorType::Forbidden, 403))
+}
+
+/// The session's whole buffer, for a client whose copy diverged.
+async fn resync(target: &SessionTarget) -> ResponseBody {
+    let key = match target.session_key() {
+        Ok(key) => key,
+        Err(e) => {
+            return SessionError::new(e.to_string(), SessionErrorType::InvalidInput, 400).into();
+        }
+    };
+    let Some(session_management_service) = get_global_config(|config| config.clone())
+        .await
+        .session_management_service
+    else {
+        return SessionError::new(
+            "Session management service not initialized".to_string(),
+            SessionErrorType::InternalError,
+            500,
+        )
+        .into();
+    };
+    match session_management_service
+        .get_session(&key.session_id, &key.language)
+        .await
+    {
+        Ok(session) => ResponseBody::Document {
+            session_id: key.session_id,
+            language: key.language,
+            revision: session.editor.revision,
+            content: session.editor.to_string(),
+        },
+        Err(e) => SessionError::from(e).into(),
     }
+}
 
-    if let (Some(session), Some(cache)) = (session, session_cache) {
-        cache.remove_session(&session.session_id);
-        println!("Session {} removed from cache", session.session_id);
+/// The definitions in the session's buffer, for the editor's symbol sidebar.
+async fn outline(target: &SessionTarget) -> ResponseBody {
+    let (key, language) = match target
+        .get_language()
+        .and_then(|language| Ok((target.session_key()?, language)))
+    {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            return SessionError::new(e.to_string(), SessionErrorType::InvalidInput, 400).into();
+        }
+    };
+    let Some(session_management_service) = get_global_config(|config| config.clone())
+        .await
+        .session_management_service
+    else {
+        return SessionError::new(
+            "Session management service not initialized".to_string(),
+            SessionErrorType::InternalError,
+            500,
+        )
+        .into();
+    };
+    let session = match session_management_service
+        .get_session(&key.session_id, &key.language)
+        .await
+    {
+        Ok(session) => session,
+        Err(e) => return SessionError::from(e).into(),
+    };
+    let editor = &session.editor;
+    match ValidationService::outline(&language, &editor.to_string(), editor.syntax_tree.as_ref()) {
+        Ok((symbols, tree)) => {
+            if let Some(tree) = tree {
+                store_syntax_tree(&key, editor, tree).await;
+            }
+            ResponseBody::Outline {
+                session_id: key.session_id,
+                language: key.language,
+                revision: editor.revision,
+                symbols: symbols.into_iter().map(Symbol::from).collect(),
+            }
+        }
+        Err(e) => SessionError::new(e.to_string(), SessionErrorType::InternalError, 500).into(),
     }
 }
 
-async fn session_management_add() -> &'static SessionCache {
-    let session_cache_instance = get_global_config(|config| config.clone())
+/// Completions for the word before the cursor, from the session's buffer. The cursor is mapped
+/// from the client's revision to the current one, like presence.
+async fn complete(message: &CompleteMessage) -> ResponseBody {
+    let target = &message.target;
+    let (key, language) = match target
+        .get_language()
+        .and_then(|language| Ok((target.session_key()?, language)))
+    {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            return SessionError::new(e.to_string(), SessionErrorType::InvalidInput, 400).into();
+        }
+    };
+    let Some(session_management_service) = get_global_config(|config| config.clone())
+        .await
+        .session_management_service
+    else {
+        return SessionError::new(
+            "Session management service not initialized".to_string(),
+            SessionErrorType::InternalError,
+            500,
+        )
+        .into();
+    };
+    let session = match session_management_service
+        .get_session(&key.session_id, &key.language)
         .await
-        .session_cache_service;
-    match session_cache_instance {
-        Some(cache) => cache,
-        None => {
-            let session_cache = SessionCache::new();
-            let mut config = get_global_config_mut().await;
-            config.set_session_cache(session_cache);
-            // set_global_config(config);
-            session_cache
+    {
+        Ok(session) => session,
+        Err(e) => return SessionError::from(e).into(),
+    };
+    let editor = &session.editor;
+    let cursor = editor
+        .map_positions(message.revision, &[message.position])
+        .and_then(|mapped| Ok((mapped[0], editor.offset_of(&mapped[0])?)));
+    let (end, offset) = match cursor {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            return SessionError::from(session_management_models::SessionError::from(e)).into();
+        }
+    };
+    let code = editor.to_string();
+    match ValidationService::complete(&language, &code, editor.syntax_tree.as_ref(), offset) {
+        Ok((start, items, tree)) => {
+            if let Some(tree) = tree {
+                store_syntax_tree(&key, editor, tree).await;
+            }
+            ResponseBody::Completions {
+                session_id: key.session_id,
+                language: key.language,
+                revision: editor.revision,
+                start: position_at(&code, start),
+                end,
+                items,
+            }
         }
+        Err(e) => SessionError::new(e.to_string(), SessionErrorType::InternalError, 500).into(),
     }
 }
 
-async fn websocket_connection_pool_add(socket_addr: SocketAddr, session_id_value: String) {
-    let webpool_id = format!("{:?}", socket_addr);
-    let web_socket_session_pool = get_global_config(|config| config.clone())
+/// Applies an edit to its session and answers with an ack carrying the buffer's new revision,
+/// then the validation of the whole buffer; or with an error. The session's other participants
+/// get the change as applied.
+async fn handle_edit(message: &WebSocketMessage, connection: &Connection) {
+    let responses = &connection.responses;
+    let error =
+        |e: SessionError| ResponseBody::from(e).about(&message.session_id, &message.language);
+    let key = match message.session_key() {
+        Ok(key) => key,
+        Err(e) => {
+            respond(
+                responses,
+                error(SessionError::new(
+                    e.to_string(),
+                    SessionErrorType::InvalidInput,
+                    400,
+                )),
+            );
+            return;
+        }
+    };
+    if let Err(e) = ensure_editor(&key, connection) {
+        respond(responses, error(e));
+        return;
+    }
+    let manager = ConnectionManager::get_connection_manager();
+    let room = manager.room(&key);
+    let updated = {
+        // One edit of the session at a time, acked and broadcast before the next is applied
+        let _turn = room.sequencer.lock().await;
+        let updated = update_create_session(message).await;
+        if let Ok((session_key, session, change)) = &updated {
+            let revision = session.editor.revision;
+            respond(
+                responses,
+                ResponseBody::Ack {
+                    session_id: session_key.session_id.clone(),
+                    language: session_key.language.clone(),
+                    revision,
+                },
+            );
+            if let Some(code) = change {
+                room.map_presence(connection.id, code);
+                let body = ResponseBody::Change {
+                    session_id: session_key.session_id.clone(),
+                    language: session_key.language.clone(),
+                    revision,
+                    code: code.clone(),
+                };
+                room.broadcast(connection.id, &WebSocketResponse::new(body).to_json());
+            }
+        }
+        updated
+    };
+    drop(room);
+    manager.release_room(&key);
+    let (session_key, session, _) = match updated {
+        Ok(updated) => updated,
+        Err(e) => {
+            respond(responses, error(e));
+            return;
+        }
+    };
+    println!("Session {} updated", session_key.to_string());
+    // Validate the whole buffer, not just the patch
+    let diagnostics = match message.get_language() {
+        Ok(language) => validate_buffer(&session_key, &language, &session.editor).await,
+        Err(e) => vec![Diagnostic::error(&e)],
+    };
+    respond(
+        responses,
+        ResponseBody::Validation {
+            session_id: session_key.session_id,
+            language: session_key.language,
+            revision: session.editor.revision,
+            valid: diagnostics.is_empty(),
+            diagnostics,
+        },
+    );
+}
+
+/// Runs the session's buffer the way `Execute` with empty code does, in the background so the
+/// socket keeps taking edits and `cancel`. Output is streamed as `output` responses, followed
+/// by one `execution` response with the run's status.
+fn start_run(message: RunMessage, connection: &Connection) {
+    let responses = connection.responses.clone();
+    let target = &message.target;
+    let error = |e: SessionError| ResponseBody::from(e).about(&target.session_id, &target.language);
+    let key = match target.session_key() {
+        Ok(key) => key,
+        Err(e) => {
+            respond(
+                &responses,
+                error(SessionError::new(
+                    e.to_string(),
+                    SessionErrorType::InvalidInput,
+                    400,
+                )),
+            );
+            return;
+        }
+    };
+    if let Err(e) = ensure_editor(&key, connection) {
+        respond(&responses, error(e));
+        return;
+    }
+    tokio::spawn(async move {
+        let (output, mut chunks) = mpsc::unbounded_channel();
+        let options = RunOptions {
+            stdin: message.stdin,
+            limits: message.limits.map(|limits| SessionLimits {
+                timeout_seconds: limits.timeout_secs,
+                memory_mb: limits.memory_mb,
+            }),
+            output: Some(output),
+        };
+        let forward = {
+            let key = key.clone();
+            let responses = responses.clone();
+            tokio::spawn(async move {
+                while let Some(chunk) = chunks.recv().await {
+                    respond(&responses, ResponseBody::output(&key, chunk));
+                }
+            })
+        };
+        let request =
+            ValidRequest::new(key.session_id.clone(), String::new(), key.language.clone());
+        let body = match session_handler(request, options).await {
+            Ok(result) => ResponseBody::run_completed(&key, result),
+            Err(e) => {
+                let status = match e.downcast_ref::<session_management_models::SessionError>() {
+                    Some(session_management_models::SessionError::Cancelled(_)) => {
+                        RunStatus::Cancelled
+                    }
+                    _ => RunStatus::Failed,
+                };
+                ResponseBody::run_failed(&key, status, e.to_string())
+            }
+        };
+        // `session_handler` dropped the output sender, so this ends after the last chunk
+        if let Err(e) = forward.await {
+            eprintln!("Failed to forward run output: {}", e);
+        }
+        respond(&responses, body);
+    });
+}
+
+/// Cancels the session's run in progress, which then reports `cancelled`. Answers only when
+/// there is nothing to cancel.
+async fn cancel_run(target: &SessionTarget, connection: &Connection) -> Option<ResponseBody> {
+    let key = match target.session_key() {
+        Ok(key) => key,
+        Err(e) => {
+            return Some(
+                SessionError::new(e.to_string(), SessionErrorType::InvalidInput, 400).into(),
+            );
+        }
+    };
+    if let Err(e) = ensure_editor(&key, connection) {
+        return Some(e.into());
+    }
+    let session_management_service = get_global_config(|config| config.clone())
+        .await
+        .session_management_service?;
+    match session_management_service
+        .cancel_run(&key.session_id, &key.language)
+        .await
+    {
+        Ok(true) => None,
+        Ok(false) => Some(
+            SessionError::new(
+                format!("No run in progress in session '{}'", key.to_string()),
+                SessionErrorType::NotFound,
+                404,
+            )
+            .into(),
+        ),
+        Err(e) => Some(SessionError::from(e).into()),
+    }
+}
+
+fn respond(responses: &UnboundedSender<Message>, body: ResponseBody) {
+    // Fails only once the writer has stopped, i.e. the socket is gone
+    let _ = responses.send(Message::Text(WebSocketResponse::new(body).to_json()));
+}
+
+/// Diagnostics for the session's buffer. The buffer is reparsed from its last parse, which is
+/// then replaced by the new one.
+async fn validate_buffer(
+    key: &SessionKey,
+    language: &LanguageDefinition,
+    editor: &CodeEditor,
+) -> Vec<Diagnostic> {
+    let (diagnostics, tree) =
+        ValidationService::diagnose(language, &editor.to_string(), editor.syntax_tree.as_ref());
+    if let Some(tree) = tree {
+        store_syntax_tree(key, editor, tree).await;
+    }
+    diagnostics.into_iter().map(Diagnostic::from).collect()
+}
+
+// Keeps `tree`, a parse of `editor`'s buffer, for reparsing the session's buffer next time
+async fn store_syntax_tree(key: &SessionKey, editor: &CodeEditor, tree: Tree) {
+    if let Some(session_management_service) = get_global_config(|config| config.clone())
         .await
-        .websocket_seesion_pool;
-    match web_socket_session_pool {
-        Some(wsp) => {
-            wsp.add_connection(&webpool_id, session_id_value.clone());
-        }
-        None => {
-            let wsp = ConnectionManager::get_connection_manager();
-            let mut config = get_global_config_mut().await;
-            config.set_websocket_connection_manager(wsp);
-            // set_global_config(config);
+        .session_management_service
+    {
+        session_management_service
+            .store_syntax_tree(&key.session_id, &key.language, editor.revision, tree)
+            .await;
+    }
+}
+
+// The session outlives the socket: it is shared with gRPC and expires with its TTL, and runs
+// started from the socket finish on their own.
+fn close_connection(responses: &UnboundedSender<Message>) {
+    // Have the writer send a close frame to the client
+    if responses.send(Message::Close(None)).is_err() {
+        eprintln!("Error sending WebSocket close frame: writer stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::helper_services::config_service::init_test_sessions;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    fn connection() -> Connection {
+        listening_connection().0
+    }
+
+    // A connection and what is sent to it
+    fn listening_connection() -> (Connection, UnboundedReceiver<Message>) {
+        let (responses, sent) = mpsc::unbounded_channel();
+        let connection = Connection {
+            id: ConnectionManager::get_connection_manager().next_connection_id(),
+            responses,
+        };
+        (connection, sent)
+    }
+
+    fn target(session_id: &str, language: &str) -> SessionTarget {
+        SessionTarget {
+            session_id: session_id.to_string(),
+            language: language.to_string(),
+            version: None,
+        }
+    }
+
+    async fn next_response(sent: &mut UnboundedReceiver<Message>) -> ResponseBody {
+        let message = tokio::time::timeout(std::time::Duration::from_secs(5), sent.recv())
+            .await
+            .expect("no response")
+            .expect("connection closed");
+        let Message::Text(text) = message else {
+            panic!("expected a text message, got {:?}", message);
+        };
+        serde_json::from_str::<WebSocketResponse>(&text)
+            .unwrap()
+            .body
+    }
+
+    fn error_code(body: Option<ResponseBody>) -> u16 {
+        match body {
+            Some(ResponseBody::Error { code, .. }) => code,
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    fn join_as(key: &SessionKey, connection: &Connection, role: ParticipantRole) {
+        ConnectionManager::get_connection_manager().join(
+            key,
+            connection.id,
+            Participant {
+                role,
+                name: None,
+                cursor: None,
+                selection: None,
+                responses: connection.responses.clone(),
+            },
+        );
+    }
+
+    #[test]
+    fn anyone_may_change_a_session_nobody_joined() {
+        let key = SessionKey::new("nobody-joined".to_string(), "python".to_string());
+        assert!(ensure_editor(&key, &connection()).is_ok());
+    }
+
+    #[test]
+    fn only_joined_editors_may_change_a_session_with_participants() {
+        let key = SessionKey::new("joined".to_string(), "python".to_string());
+        let (editor, observer, outsider) = (connection(), connection(), connection());
+        join_as(&key, &editor, ParticipantRole::Editor);
+        join_as(&key, &observer, ParticipantRole::Observer);
+
+        assert!(ensure_editor(&key, &editor).is_ok());
+        for refused in [&observer, &outsider] {
+            let error = ensure_editor(&key, refused).unwrap_err();
+            assert!(matches!(error.error_type, SessionErrorType::Forbidden));
+        }
+
+        let manager = ConnectionManager::get_connection_manager();
+        manager.leave_all(editor.id);
+        manager.leave_all(observer.id);
+        assert!(ensure_editor(&key, &outsider).is_ok());
+    }
+
+    #[tokio::test]
+    async fn cancelling_without_a_run_in_progress_is_not_found() {
+        let sessions = init_test_sessions().await;
+        let target = target("ws-no-run", "python");
+        let key = target.session_key().unwrap();
+        sessions
+            .update_code(&key.session_id, &key.language, "print(1)")
+            .await
+            .unwrap();
+
+        assert_eq!(error_code(cancel_run(&target, &connection()).await), 404);
+        // Nor is there one in a session that doesn't exist
+        let missing = self::target("ws-missing", "python");
+        assert_eq!(error_code(cancel_run(&missing, &connection()).await), 404);
+    }
+
+    #[tokio::test]
+    async fn observers_may_neither_run_nor_cancel() {
+        init_test_sessions().await;
+        let target = target("ws-observed", "python");
+        let key = target.session_key().unwrap();
+        let (observer, mut sent) = listening_connection();
+        join_as(&key, &observer, ParticipantRole::Observer);
+
+        assert_eq!(error_code(cancel_run(&target, &observer).await), 403);
+        start_run(
+            RunMessage {
+                target: self::target("ws-observed", "python"),
+                stdin: None,
+                limits: None,
+            },
+            &observer,
+        );
+        match next_response(&mut sent).await {
+            ResponseBody::Error {
+                session_id,
+                language,
+                code,
+                ..
+            } => {
+                assert_eq!(code, 403);
+                assert_eq!(session_id.as_deref(), Some("ws-observed"));
+                assert_eq!(language.as_deref(), Some("python"));
+            }
+            other => panic!("expected an error, got {:?}", other),
+        }
+        ConnectionManager::get_connection_manager().leave_all(observer.id);
+    }
+
+    #[tokio::test]
+    async fn running_a_session_that_does_not_exist_fails() {
+        init_test_sessions().await;
+        let (connection, mut sent) = listening_connection();
+        start_run(
+            RunMessage {
+                target: target("ws-never-edited", "python"),
+                stdin: None,
+                limits: None,
+            },
+            &connection,
+        );
+        match next_response(&mut sent).await {
+            ResponseBody::Execution {
+                session_id,
+                status: RunStatus::Failed,
+                message: Some(message),
+                ..
+            } => {
+                assert_eq!(session_id, "ws-never-edited");
+                assert!(message.contains("Code must be provided"), "{}", message);
+            }
+            other => panic!("expected a failed execution, got {:?}", other),
+        }
+
+        // An unknown language is refused before anything runs
+        start_run(
+            RunMessage {
+                target: target("ws-never-edited", "cobol"),
+                stdin: None,
+                limits: None,
+            },
+            &connection,
+        );
+        match next_response(&mut sent).await {
+            ResponseBody::Error { code, language, .. } => {
+                assert_eq!(code, 400);
+                assert_eq!(language.as_deref(), Some("cobol"));
+            }
+            other => panic!("expected an error, got {:?}", other),
         }
     }
 }
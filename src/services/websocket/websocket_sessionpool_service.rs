@@ -1,46 +1,36 @@
-use crate::{
-    models::websocket_sessionpool_models::{ActivityTracker, CleanupType, Status},
-    services::helper_services::config_service::get_global_config,
-    utils::helper_utils::get_current_timestamp,
+use crate::models::{
+    session_management_models::SessionKey,
+    websocket_message_model::{
+        Code, ParticipantInfo, ParticipantRole, Position, ResponseBody, Selection,
+        WebSocketResponse,
+    },
 };
 
-pub use crate::models::websocket_sessionpool_models::ConnectionManager;
+pub use crate::models::websocket_sessionpool_models::{
+    ConnectionManager, Participant, SessionRoom,
+};
 
 use dashmap::DashMap;
 use once_cell::sync::OnceCell;
-use std::{
-    cmp::Reverse,
-    collections::BinaryHeap,
-    sync::{Arc, LazyLock, Mutex},
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
 };
+use tungstenite::Message;
 
 static WEBSOCKET_SESSION_POOL: OnceCell<ConnectionManager> = OnceCell::new();
 
-impl Ord for ActivityTracker {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.last_active.cmp(&other.last_active)
-    }
-}
-impl PartialOrd for ActivityTracker {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
-impl ActivityTracker {
-    pub fn new(last_active: u64, key: String) -> Self {
-        ActivityTracker { last_active, key }
-    }
-}
+/// Author of changes that come from outside the WebSocket, such as gRPC `Execute`; connection
+/// ids start at 1, so broadcasting from it reaches every participant.
+pub const NO_CONNECTION: u64 = 0;
 
 impl ConnectionManager {
     fn new() -> &'static ConnectionManager {
         if WEBSOCKET_SESSION_POOL.get().is_none() {
             WEBSOCKET_SESSION_POOL
                 .set(ConnectionManager {
-                    pool: LazyLock::new(|| Arc::new(DashMap::new())),
-                    connection_size: 0,
-                    activity_tracker: Mutex::new(BinaryHeap::new()),
+                    rooms: DashMap::new(),
+                    next_connection_id: AtomicU64::new(NO_CONNECTION + 1),
                 })
                 .ok();
         }
@@ -51,75 +41,289 @@ impl ConnectionManager {
         Self::new()
     }
 
-    pub fn add_connection(&self, key: &str, value: String) {
-        let status = self.pool.insert(key.to_string(), Status::new(value));
-        let activity_record = ActivityTracker::new(status.unwrap().last_active, key.to_string());
-        self.activity_tracker
-            .lock()
-            .unwrap()
-            .push(Reverse(activity_record));
-        self.cleanup(CleanupType::Full);
+    /// Id for a new WebSocket connection, unique while the server runs.
+    pub fn next_connection_id(&self) -> u64 {
+        self.next_connection_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// The session's room, created if needed. Call `release_room` once done with it.
+    pub fn room(&self, key: &SessionKey) -> Arc<SessionRoom> {
+        self.rooms.entry(key.clone()).or_default().clone()
     }
 
-    pub fn get_connection(self, key: &str) -> Option<Status> {
-        self.pool.get(key).map(|status| status.clone())
+    /// Adds (or re-adds with a new role) the connection to the session's participants and
+    /// tells the others. Returns the others.
+    pub fn join(
+        &self,
+        key: &SessionKey,
+        connection: u64,
+        participant: Participant,
+    ) -> Vec<ParticipantInfo> {
+        let joined = ResponseBody::ParticipantJoined {
+            session_id: key.session_id.clone(),
+            language: key.language.clone(),
+            participant: participant.info(connection),
+        };
+        // Inserted under the map's entry lock, so `release_room` can't drop the room meanwhile
+        let room = self.rooms.entry(key.clone()).or_default();
+        let others = {
+            let mut participants = room.participants.lock().unwrap();
+            participants.insert(connection, participant);
+            participants
+                .iter()
+                .filter(|(id, _)| **id != connection)
+                .map(|(id, other)| other.info(*id))
+                .collect()
+        };
+        room.broadcast(connection, &WebSocketResponse::new(joined).to_json());
+        others
     }
 
-    pub fn remove_connection(&self, key: &str) {
-        self.pool.remove(key);
-        self.cleanup(CleanupType::Search {
-            key: key.to_string(),
+    /// Removes the connection from the session's participants and tells the others; false if
+    /// it hadn't joined.
+    pub fn leave(&self, key: &SessionKey, connection: u64) -> bool {
+        let left = self.rooms.get(key).is_some_and(|room| {
+            let left = room
+                .participants
+                .lock()
+                .unwrap()
+                .remove(&connection)
+                .is_some();
+            if left {
+                let body = ResponseBody::ParticipantLeft {
+                    session_id: key.session_id.clone(),
+                    language: key.language.clone(),
+                    participant: connection,
+                };
+                room.broadcast(connection, &WebSocketResponse::new(body).to_json());
+            }
+            left
         });
+        self.release_room(key);
+        left
+    }
+
+    /// Records where the participant's cursor and selection are, at the buffer's current
+    /// revision, and returns it as the others see it; `None` if the connection hasn't joined.
+    pub fn update_presence(
+        &self,
+        key: &SessionKey,
+        connection: u64,
+        cursor: Option<Position>,
+        selection: Option<Selection>,
+    ) -> Option<ParticipantInfo> {
+        let room = self.rooms.get(key)?;
+        let mut participants = room.participants.lock().unwrap();
+        let participant = participants.get_mut(&connection)?;
+        participant.cursor = cursor;
+        participant.selection = selection;
+        Some(participant.info(connection))
+    }
+
+    /// Removes the connection from every session it joined, when its socket closes.
+    pub fn leave_all(&self, connection: u64) {
+        let joined: Vec<SessionKey> = self
+            .rooms
+            .iter()
+            .filter(|room| room.participants.lock().unwrap().contains_key(&connection))
+            .map(|room| room.key().clone())
+            .collect();
+        for key in joined {
+            self.leave(&key, connection);
+        }
+    }
+
+    /// The connection's role in the session, `None` if it hasn't joined.
+    pub fn role(&self, key: &SessionKey, connection: u64) -> Option<ParticipantRole> {
+        let room = self.rooms.get(key)?;
+        let participants = room.participants.lock().unwrap();
+        participants
+            .get(&connection)
+            .map(|participant| participant.role)
+    }
+
+    /// Whether any connection has joined the session.
+    pub fn has_participants(&self, key: &SessionKey) -> bool {
+        self.rooms
+            .get(key)
+            .is_some_and(|room| !room.participants.lock().unwrap().is_empty())
+    }
+
+    /// Drops the session's room once nobody has joined it and no edit holds it.
+    pub fn release_room(&self, key: &SessionKey) {
+        self.rooms.remove_if(key, |_, room| {
+            Arc::strong_count(room) == 1 && room.participants.lock().unwrap().is_empty()
+        });
+    }
+}
+
+impl Participant {
+    pub fn info(&self, connection: u64) -> ParticipantInfo {
+        ParticipantInfo {
+            participant: connection,
+            name: self.name.clone(),
+            role: self.role,
+            cursor: self.cursor,
+            selection: self.selection,
+        }
     }
+}
 
-    async fn cleanup(&self, cleanup_type: CleanupType) {
-        match cleanup_type {
-            CleanupType::Full => {
-                // Assuming get_global_configs().WebSocketPoolConfig.max_connections exists and returns usize
-                let max_connections = get_global_config(|config| config.clone())
-                    .await
-                    .websocket_pool_config
-                    .max_connections;
-                let mut activity_tracker = self.activity_tracker.lock().unwrap();
-                while activity_tracker.len() > max_connections {
-                    if let Some(Reverse(activity)) = activity_tracker.pop() {
-                        self.pool.remove(&activity.key);
-                    }
+impl SessionRoom {
+    /// Moves every participant's cursor and selection through a change to the buffer, the way
+    /// clients move them. The author's own cursor goes past the text it inserted. A full
+    /// replacement forgets them, until participants report them again.
+    pub fn map_presence(&self, author: u64, change: &Code) {
+        let mut participants = self.participants.lock().unwrap();
+        for (connection, participant) in participants.iter_mut() {
+            match change {
+                Code::Patch { patches, .. } => {
+                    let after = *connection == author;
+                    let map = |position: Position| {
+                        patches.iter().fold(position, |position, patch| {
+                            patch.map_position(position, after)
+                        })
+                    };
+                    participant.cursor = participant.cursor.map(map);
+                    participant.selection = participant.selection.map(|selection| Selection {
+                        anchor: map(selection.anchor),
+                        head: map(selection.head),
+                    });
                 }
-            }
-            CleanupType::Search { key } => {
-                // Remove the entry with the given key from activity_tracker
-                let mut activity_tracker = self.activity_tracker.lock().unwrap();
-                let mut temp = BinaryHeap::new();
-                while let Some(Reverse(activity)) = activity_tracker.pop() {
-                    if activity.key != key {
-                        temp.push(Reverse(activity));
-                    }
+                Code::Full { .. } => {
+                    participant.cursor = None;
+                    participant.selection = None;
                 }
-                *activity_tracker = temp;
+            }
+        }
+    }
+
+    /// Sends `text` to every participant except `sender`. Participants whose socket is gone are
+    /// skipped; they are removed when their connection closes.
+    pub fn broadcast(&self, sender: u64, text: &str) {
+        let participants = self.participants.lock().unwrap();
+        for (connection, participant) in participants.iter() {
+            if *connection != sender {
+                let _ = participant.responses.send(Message::Text(text.to_string()));
             }
         }
     }
 }
 
-impl Status {
-    pub fn new(value: String) -> Self {
-        Status {
-            last_active: get_current_timestamp(),
-            value,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::websocket_message_model::Patch;
+
+    fn at(line: usize, ch: usize) -> Position {
+        Position { line, ch }
+    }
+
+    fn patch(start: Position, end: Position, text: &str) -> Code {
+        Code::Patch {
+            base_revision: 0,
+            patches: vec![Patch {
+                start,
+                end,
+                text: text.to_string(),
+            }],
         }
     }
 
-    pub fn was_last_active(&self) -> u64 {
-        self.last_active
+    // A room whose participants 1, 2, ... have these cursors and selections
+    fn room(presence: &[(Option<Position>, Option<Selection>)]) -> SessionRoom {
+        let room = SessionRoom::default();
+        let (responses, _) = tokio::sync::mpsc::unbounded_channel();
+        let mut participants = room.participants.lock().unwrap();
+        for (connection, (cursor, selection)) in (1..).zip(presence) {
+            participants.insert(
+                connection,
+                Participant {
+                    role: ParticipantRole::Editor,
+                    name: None,
+                    cursor: *cursor,
+                    selection: *selection,
+                    responses: responses.clone(),
+                },
+            );
+        }
+        drop(participants);
+        room
     }
 
-    pub fn set_last_active(&mut self) {
-        self.last_active = get_current_timestamp();
+    fn cursor(room: &SessionRoom, connection: u64) -> Option<Position> {
+        room.participants.lock().unwrap()[&connection].cursor
     }
 
-    pub fn upsert_value(&mut self, value: String) {
-        self.value = value;
-        self.set_last_active();
+    fn selection(room: &SessionRoom, connection: u64) -> Option<(Position, Position)> {
+        room.participants.lock().unwrap()[&connection]
+            .selection
+            .map(|selection| (selection.anchor, selection.head))
+    }
+
+    #[test]
+    fn an_insert_moves_the_cursors_after_it() {
+        let room = room(&[
+            (Some(at(0, 2)), None),
+            (Some(at(0, 6)), None),
+            (Some(at(0, 4)), None),
+            (Some(at(1, 0)), None),
+        ]);
+        room.map_presence(NO_CONNECTION, &patch(at(0, 4), at(0, 4), "ab"));
+
+        assert_eq!(cursor(&room, 1), Some(at(0, 2)));
+        assert_eq!(cursor(&room, 2), Some(at(0, 8)));
+        // Another participant's cursor at the insertion point stays before the text
+        assert_eq!(cursor(&room, 3), Some(at(0, 4)));
+        assert_eq!(cursor(&room, 4), Some(at(1, 0)));
+    }
+
+    #[test]
+    fn the_authors_cursor_moves_past_its_own_insert() {
+        let room = room(&[(Some(at(1, 3)), None), (Some(at(1, 3)), None)]);
+        room.map_presence(1, &patch(at(1, 3), at(1, 3), "x\nyz"));
+
+        assert_eq!(cursor(&room, 1), Some(at(2, 2)));
+        assert_eq!(cursor(&room, 2), Some(at(1, 3)));
+    }
+
+    #[test]
+    fn a_selection_spanning_a_deletion_shrinks() {
+        let spanning = Selection {
+            anchor: at(0, 8),
+            head: at(0, 0),
+        };
+        let inside = Selection {
+            anchor: at(0, 3),
+            head: at(0, 5),
+        };
+        let room = room(&[(Some(at(0, 8)), Some(spanning)), (None, Some(inside))]);
+        room.map_presence(NO_CONNECTION, &patch(at(0, 2), at(0, 6), ""));
+
+        assert_eq!(cursor(&room, 1), Some(at(0, 4)));
+        assert_eq!(selection(&room, 1), Some((at(0, 4), at(0, 0))));
+        // Deleted along with the text it selected
+        assert_eq!(selection(&room, 2), Some((at(0, 2), at(0, 2))));
+        assert_eq!(cursor(&room, 2), None);
+    }
+
+    #[test]
+    fn a_full_replacement_forgets_presence() {
+        let selected = Selection {
+            anchor: at(0, 0),
+            head: at(0, 1),
+        };
+        let room = room(&[(Some(at(0, 1)), Some(selected)), (Some(at(0, 0)), None)]);
+        room.map_presence(
+            1,
+            &Code::Full {
+                content: "new".to_string(),
+            },
+        );
+
+        for connection in [1, 2] {
+            assert_eq!(cursor(&room, connection), None);
+            assert_eq!(selection(&room, connection), None);
+        }
     }
 }
@@ -0,0 +1,13 @@
+use sha2::{Digest, Sha256};
+
+/// SHA-256 over all parts, each prefixed with its length so that different splits of the same
+/// bytes hash differently, as a 64-digit hex string. Stable across runs and builds, unlike
+/// `std::hash::DefaultHasher`.
+pub fn stable_hash(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
@@ -1,3 +1,4 @@
 pub mod docker_utils;
+pub mod hash_utils;
 pub mod helper_utils;
 pub mod tar_utils;
@@ -1,11 +1,15 @@
 use std::error::Error;
 use std::fs::File;
-use tar::Builder;
+use std::path::Path;
+use tar::{Builder, EntryType, Header};
+
+pub const PACKAGES_DIR_IN_TAR: &str = "packages";
 
 pub fn create_tar_archive(
     dockerfile_path: &str,
     tar_path: &str,
     docker_file_name: &String,
+    package_cache: Option<&str>,
 ) -> Result<String, Box<dyn Error>> {
     println!(
         "Creating tar archive for Dockerfile: {}::\n{}",
@@ -18,6 +22,25 @@ pub fn create_tar_archive(
     // Docker usually expects "Dockerfile" at the root of the build context.
     // let name_in_tar = docker_file_name;
     tar_builder.append_path_with_name(dockerfile_path, docker_file_name)?;
+
+    // Dockerfiles always `COPY packages/`, so the directory must exist even when empty.
+    match package_cache.filter(|path| Path::new(path).is_dir()) {
+        Some(cache_dir) => {
+            println!("Adding package cache {} to build context", cache_dir);
+            tar_builder.append_dir_all(PACKAGES_DIR_IN_TAR, cache_dir)?;
+        }
+        None => {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            tar_builder.append_data(
+                &mut header,
+                format!("{}/", PACKAGES_DIR_IN_TAR),
+                std::io::empty(),
+            )?;
+        }
+    }
     tar_builder.finish()?;
     println!(
         "Tar archive created at {} containing {} from {}",